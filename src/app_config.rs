@@ -1,22 +1,63 @@
 use std::collections::HashMap;
+use std::io::Error;
+use std::io::ErrorKind;
+use std::time::Duration;
+use std::time::SystemTime;
+
+use regex::Regex;
 
 use yaml_rust::Yaml;
 use yaml_rust::YamlLoader;
 
 use crate::AppResult;
+use crate::checkpoint::CheckpointConfig;
+use crate::chunker::ChunkingConfig;
+use crate::comparison::Comparison;
+use crate::hash_algorithm::HashAlgorithm;
+use crate::retry_policy::RetryPolicy;
+use crate::route::RouteConfig;
+use crate::s3_backend::S3Config;
+use crate::state_lock::LockConfig;
+use crate::state_compression::StateCompression;
+use crate::sftp_backend::SftpConfig;
+use crate::walk_filter::AttributeFilter;
+use crate::walk_filter::SymlinkPolicy;
+use crate::utils::parse_datetime;
+use crate::utils::parse_duration;
+use crate::utils::parse_size;
 use crate::utils::replace_variables;
 
 pub struct AppConfig {
     pub source_dir: String,
     pub state_file: String,
+    pub state_compression: StateCompression,
+    pub backend: String,
+    pub target_dir: String,
+    pub s3: S3Config,
+    pub sftp: SftpConfig,
+    pub hash_cache_file: String,
+    pub hash_algorithm: HashAlgorithm,
     pub overlay_mode: bool,
-    pub fast_comparison: bool,
+    pub comparison: Comparison,
+    pub mtime_tolerance: Duration,
     pub use_local_state: bool,
     pub use_remote_state: bool,
     pub state_indent: u32,
+    pub state_backups: u32,
+    pub checkpoint: CheckpointConfig,
+    pub lock: Option<LockConfig>,
     pub threads: u32,
+    pub fail_fast: bool,
+    pub keep_going: bool,
     pub command_workdir: String,
     pub file_filters: Vec<String>,
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    pub ignore_files: Vec<String>,
+    pub attribute_filter: AttributeFilter,
+    pub symlinks: SymlinkPolicy,
+    pub routes: Vec<RouteConfig>,
+    pub chunking: Option<ChunkingConfig>,
     pub variables: HashMap<String, String>,
     pub start_up: Vec<Vec<String>>,
     pub clean_up: Vec<Vec<String>>,
@@ -26,6 +67,22 @@ pub struct AppConfig {
     pub delete_dir: Vec<Vec<String>>,
     pub upload_file: Vec<Vec<String>>,
     pub upload_dir: Vec<Vec<String>>,
+    pub move_file: Vec<Vec<String>>,
+    pub upload_symlink: Vec<Vec<String>>,
+    pub chmod_file: Vec<Vec<String>>,
+    pub upload_chunk: Vec<Vec<String>>,
+    pub upload_manifest: Vec<Vec<String>>,
+    pub acquire_lock: Vec<Vec<String>>,
+    pub release_lock: Vec<Vec<String>>,
+    pub delete_file_retry: RetryPolicy,
+    pub delete_dir_retry: RetryPolicy,
+    pub upload_file_retry: RetryPolicy,
+    pub upload_dir_retry: RetryPolicy,
+    pub move_file_retry: RetryPolicy,
+    pub upload_symlink_retry: RetryPolicy,
+    pub chmod_file_retry: RetryPolicy,
+    pub upload_chunk_retry: RetryPolicy,
+    pub upload_manifest_retry: RetryPolicy,
 }
 
 impl AppConfig {
@@ -35,16 +92,60 @@ impl AppConfig {
         let doc = (&doc[0]).clone();
         let source_dir = doc["source-dir"].as_str().expect("the config field 'source-dir' must be present").to_owned();
         let state_file = doc["state-file"].as_str().unwrap_or(".state.json").to_owned();
+        // auto: 根据状态文件的扩展名决定(.gz为gzip，.zst为zstd)
+        let state_compression = match doc["state-compression"].as_str().unwrap_or("auto") {
+            "auto" => StateCompression::from_path(&state_file),
+            name => StateCompression::from_name(name)
+                .ok_or_else(|| Error::new(ErrorKind::InvalidInput, format!("unsupported state-compression: {}", name)))?,
+        };
+        let backend = doc["backend"].as_str().unwrap_or("").to_owned();
+        let target_dir = doc["target-dir"].as_str().unwrap_or("").to_owned();
+        let s3 = AppConfig::parse_s3_config(&doc["s3"]);
+        let sftp = AppConfig::parse_sftp_config(&doc["sftp"]);
+        let hash_cache_file = doc["hash-cache-file"].as_str().unwrap_or("").to_owned();
+        let hash_algorithm = doc["hash-algorithm"].as_str().unwrap_or("sha1");
+        let hash_algorithm = HashAlgorithm::from_name(hash_algorithm)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, format!("unsupported hash-algorithm: {}", hash_algorithm)))?;
         let overlay_mode = doc["overlay-mode"].as_bool().unwrap_or(false);
+        // 未配置comparison时沿用旧的fast-comparison选项
         let fast_comparison = doc["fast-comparison"].as_bool().unwrap_or(false);
+        let comparison = doc["comparison"].as_str().unwrap_or(if fast_comparison { "mtime" } else { "hash" });
+        let comparison = Comparison::from_name(comparison)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, format!("unsupported comparison: {}", comparison)))?;
+        let mtime_tolerance = AppConfig::parse_as_duration(&doc["mtime-tolerance"], "mtime-tolerance")?.unwrap_or(Duration::ZERO);
         let use_local_state = doc["use-local-state"].as_bool().unwrap_or(false);
         let use_remote_state = doc["use-remote-state"].as_bool().unwrap_or(true);
         let state_indent = doc["state-indent"].as_i64().map_or_else(|| 0, |v| v as u32);
+        let state_backups = doc["state-backups"].as_i64().map_or(0, |v| v.max(0) as u32);
+        let lock = AppConfig::parse_lock(&doc["lock"])?;
+        let checkpoint = CheckpointConfig {
+            operations: doc["checkpoint"]["operations"].as_i64().map_or(0, |v| v.max(0) as u64),
+            interval: AppConfig::parse_as_duration(&doc["checkpoint"]["interval"], "checkpoint.interval")?.filter(|d| !d.is_zero()),
+            upload_state: doc["checkpoint"]["upload-state"].as_bool().unwrap_or(false),
+        };
         let threads = doc["threads"].as_i64().map_or_else(|| 1, |v| v as u32);
+        let fail_fast = doc["fail-fast"].as_bool().unwrap_or(true);
+        let keep_going = doc["keep-going"].as_bool().unwrap_or(false);
         let command_workdir = doc["command-workdir"].as_str().unwrap_or("").to_owned();
         let file_filters: Vec<String> = doc["file-filters"]
             .as_vec()
             .map_or_else(|| Vec::new(), |f| f.iter().map(|v| v.as_str().unwrap_or("").to_owned()).collect());
+        let include = AppConfig::parse_as_string_list(&doc["include"]);
+        let exclude = AppConfig::parse_as_string_list(&doc["exclude"]);
+        let ignore_files = AppConfig::parse_as_string_list(&doc["ignore-files"]);
+        let attribute_filter = AttributeFilter {
+            max_size: AppConfig::parse_as_size(&doc["max-size"], "max-size")?,
+            min_size: AppConfig::parse_as_size(&doc["min-size"], "min-size")?,
+            modified_after: AppConfig::parse_as_time(&doc["modified-after"], "modified-after")?,
+            modified_before: AppConfig::parse_as_time(&doc["modified-before"], "modified-before")?,
+            skip_hidden: doc["skip-hidden"].as_bool().unwrap_or(false),
+            skip_empty_files: doc["skip-empty-files"].as_bool().unwrap_or(false),
+        };
+        let symlinks = doc["symlinks"].as_str().unwrap_or("follow");
+        let symlinks = SymlinkPolicy::from_name(symlinks)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, format!("unsupported symlinks policy: {}", symlinks)))?;
+        let routes = AppConfig::parse_routes(&doc["routes"])?;
+        let chunking = AppConfig::parse_chunking(&doc["chunking"])?;
         let variables = doc["variables"].clone();
         let command_node = &doc["commands"];
         let start_up = AppConfig::parse_as_command_line(&command_node["start-up"]);
@@ -55,6 +156,22 @@ impl AppConfig {
         let delete_dir = AppConfig::parse_as_command_line(&command_node["delete-dir"]);
         let upload_file = AppConfig::parse_as_command_line(&command_node["upload-file"]);
         let upload_dir = AppConfig::parse_as_command_line(&command_node["making-dir"]);
+        let move_file = AppConfig::parse_as_command_line(&command_node["move-file"]);
+        let upload_symlink = AppConfig::parse_as_command_line(&command_node["upload-symlink"]);
+        let chmod_file = AppConfig::parse_as_command_line(&command_node["chmod-file"]);
+        let upload_chunk = AppConfig::parse_as_command_line(&command_node["upload-chunk"]);
+        let upload_manifest = AppConfig::parse_as_command_line(&command_node["upload-manifest"]);
+        let acquire_lock = AppConfig::parse_as_command_line(&command_node["acquire-lock"]);
+        let release_lock = AppConfig::parse_as_command_line(&command_node["release-lock"]);
+        let delete_file_retry = AppConfig::parse_retry_policy(&command_node["delete-file"]["retry"])?;
+        let delete_dir_retry = AppConfig::parse_retry_policy(&command_node["delete-dir"]["retry"])?;
+        let upload_file_retry = AppConfig::parse_retry_policy(&command_node["upload-file"]["retry"])?;
+        let upload_dir_retry = AppConfig::parse_retry_policy(&command_node["making-dir"]["retry"])?;
+        let move_file_retry = AppConfig::parse_retry_policy(&command_node["move-file"]["retry"])?;
+        let upload_symlink_retry = AppConfig::parse_retry_policy(&command_node["upload-symlink"]["retry"])?;
+        let chmod_file_retry = AppConfig::parse_retry_policy(&command_node["chmod-file"]["retry"])?;
+        let upload_chunk_retry = AppConfig::parse_retry_policy(&command_node["upload-chunk"]["retry"])?;
+        let upload_manifest_retry = AppConfig::parse_retry_policy(&command_node["upload-manifest"]["retry"])?;
 
         // 全局变量
         let variables: HashMap<String, String> = variables.as_hash().map_or_else(|| HashMap::new(), |v| {
@@ -63,18 +180,39 @@ impl AppConfig {
 
         // 替换变量
         let source_dir = replace_variables(&source_dir, &variables);
+        let target_dir = replace_variables(&target_dir, &variables);
 
         Ok(AppConfig {
             source_dir,
             state_file,
+            state_compression,
+            backend,
+            target_dir,
+            s3,
+            sftp,
+            hash_cache_file,
+            hash_algorithm,
             overlay_mode,
-            fast_comparison,
+            comparison,
+            mtime_tolerance,
             use_local_state,
             use_remote_state,
             state_indent,
+            state_backups,
+            checkpoint,
+            lock,
             threads,
+            fail_fast,
+            keep_going,
             command_workdir,
             file_filters,
+            include,
+            exclude,
+            ignore_files,
+            attribute_filter,
+            symlinks,
+            routes,
+            chunking,
             variables,
             start_up,
             clean_up,
@@ -84,10 +222,31 @@ impl AppConfig {
             delete_dir,
             upload_file,
             upload_dir,
+            move_file,
+            upload_symlink,
+            chmod_file,
+            upload_chunk,
+            upload_manifest,
+            acquire_lock,
+            release_lock,
+            delete_file_retry,
+            delete_dir_retry,
+            upload_file_retry,
+            upload_dir_retry,
+            move_file_retry,
+            upload_symlink_retry,
+            chmod_file_retry,
+            upload_chunk_retry,
+            upload_manifest_retry,
         })
     }
 
     fn parse_as_command_line(yaml: &Yaml) -> Vec<Vec<String>> {
+        // 写成对象形式时，命令行位于command字段中，其它字段为附加选项(比如retry)
+        if yaml.as_hash().is_some() {
+            return AppConfig::parse_as_command_line(&yaml["command"]);
+        }
+
         if !yaml.is_array() {
             let line = yaml.as_str().unwrap_or("").to_owned();
             return if line.is_empty() { vec![] } else { vec![vec![line]] };
@@ -109,4 +268,166 @@ impl AppConfig {
         
         array
     }
+
+    fn parse_as_string_list(yaml: &Yaml) -> Vec<String> {
+        yaml.as_vec().map_or_else(Vec::new, |f| f.iter().filter_map(|v| v.as_str()).map(|v| v.to_owned()).collect())
+    }
+
+    fn parse_routes(yaml: &Yaml) -> AppResult<Vec<RouteConfig>> {
+        let mut routes = Vec::new();
+
+        for route in yaml.as_vec().map_or(&[][..], |v| &v[..]) {
+            routes.push(RouteConfig {
+                file_filters: AppConfig::parse_as_string_list(&route["file-filters"]),
+                include: AppConfig::parse_as_string_list(&route["include"]),
+                exclude: AppConfig::parse_as_string_list(&route["exclude"]),
+                upload_file: AppConfig::parse_as_command_line(&route["upload-file"]),
+                delete_file: AppConfig::parse_as_command_line(&route["delete-file"]),
+                upload_file_retry: AppConfig::parse_retry_policy(&route["upload-file"]["retry"])?,
+                delete_file_retry: AppConfig::parse_retry_policy(&route["delete-file"]["retry"])?,
+            });
+        }
+
+        Ok(routes)
+    }
+
+    /// 解析分块上传的配置，没有chunking配置或者enabled为false时不启用分块上传
+    fn parse_chunking(yaml: &Yaml) -> AppResult<Option<ChunkingConfig>> {
+        if yaml.as_hash().is_none() || !yaml["enabled"].as_bool().unwrap_or(true) {
+            return Ok(None);
+        }
+
+        let min_file_size = AppConfig::parse_as_size(&yaml["min-file-size"], "chunking.min-file-size")?.unwrap_or(64 * 1024 * 1024);
+        let avg_chunk_size = AppConfig::parse_as_size(&yaml["avg-chunk-size"], "chunking.avg-chunk-size")?.unwrap_or(4 * 1024 * 1024);
+
+        if avg_chunk_size < 64 {
+            return Err(Box::new(Error::new(ErrorKind::InvalidInput, format!("chunking.avg-chunk-size is too small: {}", avg_chunk_size))));
+        }
+
+        Ok(Some(ChunkingConfig { min_file_size, avg_chunk_size }))
+    }
+
+    /// 解析状态文件锁的配置，没有lock配置或者enabled为false时不使用锁
+    fn parse_lock(yaml: &Yaml) -> AppResult<Option<LockConfig>> {
+        if yaml.as_hash().is_none() || !yaml["enabled"].as_bool().unwrap_or(true) {
+            return Ok(None);
+        }
+
+        Ok(Some(LockConfig {
+            timeout: AppConfig::parse_as_duration(&yaml["timeout"], "lock.timeout")?.unwrap_or(Duration::ZERO),
+            stale_after: AppConfig::parse_as_duration(&yaml["stale-after"], "lock.stale-after")?.filter(|d| !d.is_zero()),
+        }))
+    }
+
+    fn parse_s3_config(yaml: &Yaml) -> S3Config {
+        let region = yaml["region"].as_str().map(|v| v.to_owned())
+            .or_else(|| std::env::var("AWS_REGION").ok())
+            .unwrap_or_else(|| "us-east-1".to_owned());
+
+        let endpoint = yaml["endpoint"].as_str().unwrap_or("").to_owned();
+        let path_style = yaml["path-style"].as_bool().unwrap_or_else(|| S3Config::default_path_style(&endpoint));
+
+        S3Config {
+            endpoint,
+            bucket: yaml["bucket"].as_str().unwrap_or("").to_owned(),
+            prefix: yaml["prefix"].as_str().unwrap_or("").to_owned(),
+            region,
+            path_style,
+        }
+    }
+
+    fn parse_sftp_config(yaml: &Yaml) -> SftpConfig {
+        SftpConfig {
+            host: yaml["host"].as_str().unwrap_or("").to_owned(),
+            port: yaml["port"].as_i64().map_or_else(|| 22, |v| v as u16),
+            username: yaml["username"].as_str().unwrap_or("").to_owned(),
+            private_key: yaml["private-key"].as_str().unwrap_or("").to_owned(),
+            known_hosts: yaml["known-hosts"].as_str().unwrap_or("~/.ssh/known_hosts").to_owned(),
+            accept_unknown_host_keys: yaml["accept-unknown-host-keys"].as_bool().unwrap_or(false),
+        }
+    }
+
+    fn parse_retry_policy(yaml: &Yaml) -> AppResult<RetryPolicy> {
+        if yaml.is_badvalue() || yaml.is_null() {
+            return Ok(RetryPolicy::none());
+        }
+
+        let attempts = yaml["attempts"].as_i64().map_or_else(|| 3, |v| v.max(1) as u32);
+        let backoff = AppConfig::parse_as_duration(&yaml["backoff"], "retry.backoff")?.unwrap_or(Duration::from_secs(1));
+        let max_backoff = AppConfig::parse_as_duration(&yaml["max-backoff"], "retry.max-backoff")?.unwrap_or(Duration::from_secs(60));
+        let exit_codes = yaml["exit-codes"]
+            .as_vec()
+            .map_or_else(Vec::new, |f| f.iter().filter_map(|v| v.as_i64()).map(|v| v as i32).collect());
+        let stderr_pattern = match yaml["stderr-pattern"].as_str() {
+            Some(pattern) => Some(Regex::new(pattern).map_err(|e| Error::new(ErrorKind::InvalidInput, format!("retry.stderr-pattern: {}", e)))?),
+            None => None,
+        };
+
+        Ok(RetryPolicy {
+            attempts,
+            backoff,
+            max_backoff: max_backoff.max(backoff),
+            exit_codes,
+            stderr_pattern,
+        })
+    }
+
+    /// 解析文件大小，数字按字节处理，字符串支持K, M, G等后缀
+    fn parse_as_size(yaml: &Yaml, field: &str) -> AppResult<Option<u64>> {
+        if let Some(size) = yaml.as_i64() {
+            return Ok(Some(size.max(0) as u64));
+        }
+
+        match yaml.as_str() {
+            Some(text) => match parse_size(text) {
+                Some(size) => Ok(Some(size)),
+                None => Err(Box::new(Error::new(ErrorKind::InvalidInput, format!("invalid size for {}: {}", field, text)))),
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// 解析时间点，返回unix时间戳(秒)<br/>
+    /// 数字为unix时间戳，字符串可以是UTC日期时间(2022-01-31 08:00:00)，或者相对于现在的时间长度(比如10m表示10分钟前)
+    fn parse_as_time(yaml: &Yaml, field: &str) -> AppResult<Option<u64>> {
+        if let Some(timestamp) = yaml.as_i64() {
+            return Ok(Some(timestamp.max(0) as u64));
+        }
+
+        let text = match yaml.as_str() {
+            Some(text) => text,
+            None => return Ok(None),
+        };
+
+        if let Some(timestamp) = parse_datetime(text) {
+            return Ok(Some(timestamp));
+        }
+
+        match parse_duration(text) {
+            Some(duration) => {
+                let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap();
+                Ok(Some(now.saturating_sub(duration).as_secs()))
+            },
+            None => Err(Box::new(Error::new(ErrorKind::InvalidInput, format!("invalid time for {}: {}", field, text)))),
+        }
+    }
+
+    /// 解析时间长度，数字按秒处理，字符串支持ms, s, m, h后缀
+    fn parse_as_duration(yaml: &Yaml, field: &str) -> AppResult<Option<Duration>> {
+        if let Some(seconds) = yaml.as_i64() {
+            return Ok(Some(Duration::from_secs(seconds.max(0) as u64)));
+        }
+
+        if let Some(seconds) = yaml.as_f64() {
+            return Ok(Some(Duration::from_secs_f64(seconds.max(0.0))));
+        }
+
+        match yaml.as_str() {
+            Some(text) => match parse_duration(text) {
+                Some(duration) => Ok(Some(duration)),
+                None => Err(Box::new(Error::new(ErrorKind::InvalidInput, format!("invalid duration for {}: {}", field, text)))),
+            },
+            None => Ok(None),
+        }
+    }
 }
\ No newline at end of file
@@ -8,6 +8,11 @@ pub struct AppOptions {
     pub debug: bool,
     pub dryrun: bool,
     pub test_filter: bool,
+    pub keep_going: bool,
+    pub plan: Option<String>,
+    pub plan_output: Option<String>,
+    pub apply_plan: Option<String>,
+    pub restore_state: Option<u32>,
 }
 
 impl AppOptions {
@@ -24,10 +29,34 @@ impl AppOptions {
                 .help("show command line before executing"))
             .arg(Arg::new("dry-run")
                 .long("dry-run")
-                .help("run but do not execute any commands actually"))
+                .help("run but do not execute any commands actually, same as '--plan text'"))
             .arg(Arg::new("test-filter")
                 .long("test-filter")
-                .help("the all the file-filters's matchings"));
+                .help("the all the file-filters's matchings"))
+            .arg(Arg::new("keep-going")
+                .long("keep-going")
+                .help("continue with the remaining files when some operations fail"))
+            .arg(Arg::new("plan")
+                .long("plan")
+                .takes_value(true)
+                .possible_values(["json", "yaml", "text"])
+                .help("print the operations that would be executed and exit without changing anything"))
+            .arg(Arg::new("plan-output")
+                .long("plan-output")
+                .takes_value(true)
+                .help("write the plan to a file instead of the standard output"))
+            .arg(Arg::new("apply-plan")
+                .long("apply-plan")
+                .takes_value(true)
+                .conflicts_with_all(&["plan", "dry-run"])
+                .help("execute the operations in a plan file created by '--plan json' instead of comparing the files again"))
+            .arg(Arg::new("restore-state")
+                .long("restore-state")
+                .takes_value(true)
+                .value_name("N")
+                .validator(|v| v.parse::<u32>().map_err(|e| e.to_string()).and_then(|v| if v > 0 { Ok(()) } else { Err("must be at least 1".to_owned()) }))
+                .conflicts_with_all(&["plan", "dry-run", "apply-plan", "test-filter"])
+                .help("replace the state file with the N-th backup (see the config field 'state-backups') and exit"));
             
         let matches = command.get_matches();
 
@@ -35,12 +64,23 @@ impl AppOptions {
         let arg_debug = matches.is_present("debug");
         let arg_dryrun = matches.is_present("dry-run");
         let arg_test_filter = matches.is_present("test-filter");
+        let arg_keep_going = matches.is_present("keep-going");
+        let arg_plan = matches.value_of("plan").map(|v| v.to_owned())
+            .or_else(|| if arg_dryrun { Some("text".to_owned()) } else { None });
+        let arg_plan_output = matches.value_of("plan-output").map(|v| v.to_owned());
+        let arg_apply_plan = matches.value_of("apply-plan").map(|v| v.to_owned());
+        let arg_restore_state = matches.value_of("restore-state").map(|v| v.parse::<u32>().unwrap());
 
         AppOptions {
             config: arg_config,
             debug: arg_debug,
             dryrun: arg_dryrun,
             test_filter: arg_test_filter,
+            keep_going: arg_keep_going,
+            plan: arg_plan,
+            plan_output: arg_plan_output,
+            apply_plan: arg_apply_plan,
+            restore_state: arg_restore_state,
         }
     }
 }
\ No newline at end of file
@@ -1,30 +1,88 @@
 use std::cell::Cell;
+use std::collections::HashMap;
+use std::collections::HashSet;
 use std::env;
+use std::fs;
 use std::io::Error;
 use std::io::ErrorKind;
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::sync::Mutex;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
 
 use crate::AppResult;
 use crate::app_config::AppConfig;
 use crate::app_options::AppOptions;
+use crate::backend;
+use crate::backend::Backend;
 use crate::blocking_thread_pool::BlockingThreadPool;
+use crate::checkpoint::Checkpoint;
+use crate::chunker::Chunk;
+use crate::chunker::KnownChunks;
+use crate::chunker::chunk_file;
+use crate::chunker::is_run_temp_dir;
+use crate::chunker::manifest_file;
+use crate::chunker::run_temp_dir;
+use crate::chunker::write_chunk;
+use crate::chunker::write_manifest;
+use crate::comparison::Comparison;
+use crate::failure_report::FailureReport;
+use crate::failure_report::MAKE_DIR;
+use crate::differences::Differences;
 use crate::file::File;
 use crate::file_comparer::FileComparer;
 use crate::file_state::State;
 use crate::hash_cache::HashCache;
+use crate::interrupt;
+use crate::plan::Plan;
+use crate::plan::PlannedOperation;
+use crate::progress;
+use crate::retry_policy::RetryPolicy;
+use crate::state_compression::StateCompression;
+use crate::state_lock::StateLock;
+use crate::route::Route;
+use crate::route::RouteAction;
+use crate::route::find_route;
 use crate::rule_filter::RuleFilter;
 use crate::simple_file::FileData;
+use crate::simple_file::read_link_target;
 use crate::subprocess_task::SubprocessResult;
 use crate::subprocess_task::SubprocessTask;
+use crate::utils;
 use crate::variable_replace::VariableReplace;
+use crate::walk_filter::WalkFilter;
+
+/// 等待其它进程释放状态文件的锁时，每次重试之间的间隔
+const LOCK_RETRY_INTERVAL: Duration = Duration::from_secs(2);
+
+/// 在线程池中对单个文件执行的操作
+type Job = dyn Fn(&VariableReplace) -> std::io::Result<()> + Send + Sync;
 
 pub struct App {
     options: AppOptions,
     config: AppConfig,
     variables: VariableReplace,
     hash_cache: Arc<HashCache>,
+    backend: Option<Arc<dyn Backend>>,
+    failures: Arc<FailureReport>,
     file_filter: RuleFilter,
+    walk_filter: WalkFilter,
+    routes: Arc<Vec<Route>>,
+    checkpoint: Arc<Checkpoint>,
+    /// 本次运行是否已经轮换过状态文件的备份，每次运行只轮换一次(检查点不会产生新的备份)
+    state_rotated: AtomicBool,
+    /// 当前进程的标识，记录在锁文件中，也用于acquire-lock和release-lock命令
+    lock_owner: String,
+    /// 本地锁文件(开启lock时)
+    state_lock: Mutex<Option<StateLock>>,
+    /// 是否已经通过acquire-lock命令获取了远端的锁
+    remote_locked: AtomicBool,
+    /// 本次运行存放分块和清单的临时目录，执行计划文件时使用计划中记录的目录
+    chunk_dir: PathBuf,
     sourcedir: File,
     workdir: File,
 }
@@ -55,8 +113,22 @@ impl App {
             return Err(Box::new(Error::new(ErrorKind::NotFound, String::from(format!("the workdir is not a dir: {}", workdir.path())))))
         }
 
-        let hash_cache = Arc::new(HashCache::new(&sourcedir));
-        let file_filter = RuleFilter::new(&config.file_filters)?;
+        let hash_cache = Arc::new(HashCache::new(&sourcedir, config.hash_algorithm));
+        let file_filter = RuleFilter::new_with_globs(&config.file_filters, &config.include, &config.exclude)?;
+        let walk_filter = WalkFilter::new(&sourcedir, &config.ignore_files, &config.attribute_filter, config.symlinks);
+        let backend = backend::from_config(&config)?;
+
+        if config.chunking.is_some() {
+            if backend.is_some() {
+                return Err(Box::new(Error::new(ErrorKind::InvalidInput, "chunking is not supported by the built-in backends, use the commands 'upload-chunk' and 'upload-manifest' instead")));
+            }
+            if config.upload_chunk.is_empty() || config.upload_manifest.is_empty() {
+                return Err(Box::new(Error::new(ErrorKind::InvalidInput, "chunking requires both of the commands 'upload-chunk' and 'upload-manifest'")));
+            }
+        }
+
+        let routes = config.routes.iter().map(Route::new).collect::<AppResult<Vec<Route>>>()?;
+        let checkpoint = Arc::new(Checkpoint::new(&config.checkpoint));
 
         let mut variables = VariableReplace::new();
         variables.variables.extend(config.variables.to_owned());
@@ -71,68 +143,233 @@ impl App {
             config,
             variables,
             hash_cache,
+            backend,
+            failures: Arc::new(FailureReport::new()),
             file_filter,
+            walk_filter,
+            routes: Arc::new(routes),
+            checkpoint,
+            state_rotated: AtomicBool::new(false),
+            lock_owner: StateLock::owner_id(),
+            state_lock: Mutex::new(None),
+            remote_locked: AtomicBool::new(false),
+            chunk_dir: run_temp_dir(),
             sourcedir,
             workdir,
         })
     }
 
+    /// tasks: 每个任务的文件路径(用于报告失败)和对应的变量<br/>
+    /// job: 在线程池中对每个任务执行的操作
     fn execute_multiple_thread(
         &self, 
-        commands: &Vec<Vec<String>>, 
-        parallel: usize, 
-        varses: &Vec<VariableReplace>,
+        operation: &str,
+        tasks: &Vec<(String, VariableReplace)>,
+        job: Arc<Job>,
         before_execute: Box<dyn Fn(&VariableReplace) + Send + Sync>,
         after_execute: Box<dyn Fn(&VariableReplace) + Send + Sync>
     ) -> AppResult<()> {
-        let mut pool = BlockingThreadPool::new(parallel);
+        let keep_going = self.is_keep_going();
+        let mut pool = BlockingThreadPool::new(self.config.threads as usize, self.config.fail_fast && !keep_going);
         let after_execute = Arc::new(after_execute);
 
-        for vars in varses {
+        for (path, vars) in tasks {
+            // 首个任务失败或者收到中断信号后不再派发剩余的任务
+            if pool.is_cancelled() || interrupt::is_interrupted() {
+                break;
+            }
+
             let vars = vars.clone();
-            let workdir = self.workdir.clone();
-            let debug = self.options.debug;
-            let commands = commands.clone();
+            let job = job.clone();
             let after_execute = after_execute.clone();
+            let checkpoint = self.checkpoint.clone();
+            let failures = self.failures.clone();
+            let operation = operation.to_owned();
+            let path = path.to_owned();
 
             before_execute(&vars);
             
             pool.execute(move || {
-                let mut last_result: Option<SubprocessResult> = None;
-                for step in commands {
-                    let mut task = SubprocessTask::from_command_line(
-                        &step, &workdir, &vars, 
-                        last_result.as_ref()).unwrap();
-        
-                    if debug {
-                        println!("> {:?}", task.raw_divided);
+                if let Err(e) = job(&vars) {
+                    // 失败的文件不执行after_execute，也就不会更新到状态里
+                    if keep_going {
+                        failures.record(&operation, &path, &e.to_string());
                     }
-        
-                    let r = task.execute(false);
-                    if r.is_err() {
-                        return Err(Box::new(r.err().unwrap()));
-                    }
-                    let r = r.unwrap();
-                    last_result = Some(r);
+
+                    return Err(Box::new(Error::new(e.kind(), format!("{}: {}", path, e))));
                 }
 
                 after_execute(&vars);
+                checkpoint.record();
 
                 Ok(())
             });
+        }
+
+        // 等待所有任务结束后统一报告失败的任务(keep-going模式下在最后一起报告)
+        let result = pool.close_and_wait();
 
-            let r = pool.close_and_wait();
+        // 收到中断信号时不再执行后续的操作，被信号终止的命令不需要再报告
+        interrupt::check()?;
 
-            if r.is_err() {
-                let err = r.err().unwrap();
-                return Err(err);
+        if let Err(errors) = result {
+            if keep_going {
+                return Ok(());
             }
+
+            progress!("\n以下{}个任务执行失败:", errors.len());
+            for e in &errors {
+                progress!("  {}", e);
+            }
+
+            return Err(Box::new(Error::other(format!("{} tasks failed", errors.len()))));
         }
 
         Ok(())
     }
 
+    /// 创建依次执行commands中每一条命令的任务
+    fn command_job(&self, commands: &[Vec<String>], retry: &RetryPolicy) -> Arc<Job> {
+        let workdir = self.workdir.clone();
+        let debug = self.options.debug;
+        let commands = commands.to_vec();
+        let retry = retry.clone();
+
+        Arc::new(move |vars| {
+            let mut last_result: Option<SubprocessResult> = None;
+            for step in &commands {
+                let mut task = SubprocessTask::from_command_line(
+                    step, &workdir, vars, 
+                    last_result.as_ref()).unwrap();
+
+                if debug {
+                    progress!("> {:?}", task.raw_divided);
+                }
+
+                let r = task.execute_with_retry(false, &retry)
+                    .map_err(|e| Error::new(e.kind(), format!("{:?}: {}", task.raw_divided, e)))?;
+                last_result = Some(r);
+            }
+
+            Ok(())
+        })
+    }
+
+    /// 创建按照routes选择命令的任务，没有匹配的路由时执行fallback，fallback为None时不执行任何操作
+    fn routed_job(&self, action: RouteAction, fallback: Option<Arc<Job>>) -> Option<Arc<Job>> {
+        let jobs = self.routes.iter()
+            .map(|r| {
+                let (commands, retry) = r.commands(action);
+                if commands.is_empty() { None } else { Some(self.command_job(commands, retry)) }
+            })
+            .collect::<Vec<Option<Arc<Job>>>>();
+
+        if jobs.iter().all(|j| j.is_none()) {
+            return fallback;
+        }
+
+        let routes = self.routes.clone();
+
+        Some(Arc::new(move |vars: &VariableReplace| {
+            let path = vars.variables.get("path").unwrap();
+            match find_route(&routes, action, path).and_then(|i| jobs[i].as_ref()) {
+                Some(job) => job(vars),
+                None => match &fallback {
+                    Some(job) => job(vars),
+                    None => Ok(()),
+                },
+            }
+        }))
+    }
+
+    /// 创建分块上传的任务，大于等于chunking.min-file-size的文件只上传远端还没有的分块，然后上传清单<br/>
+    /// 其它文件执行fallback，未启用分块上传时直接返回fallback<br/>
+    /// known_chunks: 远端已有的分块，uploaded: 上传成功的文件的分块列表，用于更新状态
+    fn chunked_job(
+        &self,
+        fallback: Option<Arc<Job>>,
+        known_chunks: Arc<KnownChunks>,
+        uploaded: Arc<Mutex<HashMap<String, Vec<Chunk>>>>
+    ) -> Option<Arc<Job>> {
+        let chunking = match &self.config.chunking {
+            Some(chunking) => chunking.clone(),
+            None => return fallback,
+        };
+
+        let upload_chunk = self.command_job(&self.config.upload_chunk, &self.config.upload_chunk_retry);
+        let upload_manifest = self.command_job(&self.config.upload_manifest, &self.config.upload_manifest_retry);
+        let sourcedir = self.sourcedir.to_owned();
+        let hash_cache = self.hash_cache.clone();
+        let chunk_dir = self.chunk_dir.to_owned();
+
+        Some(Arc::new(move |vars: &VariableReplace| {
+            let path = vars.variables.get("path").unwrap();
+            let file = sourcedir.append(path)?;
+
+            if file.length()? < chunking.min_file_size {
+                return match &fallback {
+                    Some(job) => job(vars),
+                    None => Ok(()),
+                };
+            }
+
+            let chunks = chunking.split(&file, hash_cache.algorithm())?;
+
+            let mut offset = 0;
+            for chunk in &chunks {
+                // 大文件的分块很多，收到中断信号时不再上传剩余的分块
+                interrupt::check()?;
+
+                // 检查和标记在同一个锁内完成，同一个分块只会由一个线程上传
+                if known_chunks.begin(&chunk.hash) {
+                    let result = write_chunk(&chunk_dir, &file, offset, chunk).and_then(|chunk_file| {
+                        let result = upload_chunk(&chunk_variables(vars, &chunk_file, offset, chunk));
+                        let _ = fs::remove_file(chunk_file.path());
+                        result
+                    });
+
+                    known_chunks.finish(&chunk.hash, result.is_ok());
+                    result?;
+                }
+                offset += chunk.length;
+            }
+
+            let manifest = manifest_file(&chunk_dir, path);
+            write_manifest(&manifest, &chunks)?;
+            let mut vars = vars.clone();
+            vars.add("manifest", &manifest.path());
+            let result = upload_manifest(&vars);
+            let _ = fs::remove_file(manifest.path());
+            result?;
+
+            uploaded.lock().unwrap().insert(path.to_owned(), chunks);
+
+            Ok(())
+        }))
+    }
+
+    fn is_keep_going(&self) -> bool {
+        self.options.keep_going || self.config.keep_going
+    }
+
+    /// 检查单个文件的操作结果，keep-going模式下记录失败并返回Ok(false)，否则返回原来的错误
+    fn check_result(&self, operation: &str, path: &str, result: AppResult<()>) -> AppResult<bool> {
+        match result {
+            Ok(_) => Ok(true),
+            Err(e) if self.is_keep_going() => {
+                progress!("{}失败: {}: {}", operation, path, e);
+                self.failures.record(operation, path, &e.to_string());
+                Ok(false)
+            },
+            Err(e) => Err(e),
+        }
+    }
+
     fn execute_single_thread(&self, commands: &Vec<Vec<String>>, vars: &VariableReplace) -> AppResult<()> {
+        self.execute_single_thread_with_retry(commands, vars, &RetryPolicy::none())
+    }
+
+    fn execute_single_thread_with_retry(&self, commands: &Vec<Vec<String>>, vars: &VariableReplace, retry: &RetryPolicy) -> AppResult<()> {
         let mut last_result: Option<SubprocessResult> = None;
         for step in commands {
             let mut task = SubprocessTask::from_command_line(
@@ -140,78 +377,341 @@ impl App {
                 last_result.as_ref())?;
 
             if self.options.debug {
-                println!("> {:?}", task.raw_divided);
+                progress!("> {:?}", task.raw_divided);
             }
 
-            last_result = Some(task.execute(false)?);
+            last_result = Some(task.execute_with_retry(false, retry)?);
         }
 
         Ok(())
     }
 
+    /// 需要执行删除的旧文件，覆盖模式下会被重新上传的文件不需要先删除
+    fn files_to_delete<'a>(&self, diff: &'a Differences) -> Vec<&'a str> {
+        diff.old_files
+            .iter()
+            .filter_map(|e| if self.config.overlay_mode && diff.new_files.contains(e) { None } else { Some(&e[..]) })
+            .collect::<Vec<&str>>()
+    }
+
+    /// 单个文件操作使用的变量
+    fn path_variables(&self, path: &str) -> VariableReplace {
+        let mut vars = self.variables.to_owned();
+        vars.add("path", path);
+        vars.add("path_", &path.replace("/", "\\"));
+        vars
+    }
+
+    /// 上传文件和修改权限操作使用的变量，$mode为8进制的权限位(比如: 755)，不支持权限的平台上没有此变量
+    fn file_variables(&self, path: &str) -> AppResult<VariableReplace> {
+        let mut vars = self.path_variables(path);
+        if let Some(mode) = self.sourcedir.append(path)?.mode()? {
+            vars.add("mode", &format!("{:o}", mode));
+        }
+        Ok(vars)
+    }
+
+    /// 上传符号链接操作使用的变量
+    fn symlink_variables(&self, path: &str) -> AppResult<VariableReplace> {
+        let mut vars = self.path_variables(path);
+        vars.add("link-target", &read_link_target(&self.sourcedir.append(path)?)?);
+        Ok(vars)
+    }
+
+    /// 移动文件操作使用的变量
+    fn move_variables(&self, from: &str, to: &str) -> VariableReplace {
+        let mut vars = self.variables.to_owned();
+        vars.add("from", from);
+        vars.add("to", to);
+        vars.add("from_", &from.replace('/', "\\"));
+        vars.add("to_", &to.replace('/', "\\"));
+        vars
+    }
+
     fn get_state_file(&self) -> File {
         File::new(&self.variables.apply(&self.config.state_file))
     }
 
+    fn get_hash_cache_file(&self) -> Option<File> {
+        if self.config.hash_cache_file.is_empty() {
+            None
+        } else {
+            Some(File::new(&self.variables.apply(&self.config.hash_cache_file)))
+        }
+    }
+
     pub fn load_state_from_file(&self, state_file: &File) -> AppResult<State> {
         let use_local_state = self.config.use_local_state;
         let use_remote_state = self.config.use_remote_state;
 
         let state = if use_local_state || use_remote_state {
+            // 在读取状态文件之前加锁，直到保存状态文件之后才释放
+            self.acquire_state_lock(state_file)?;
+
             if use_local_state {
-                println!("从本地加载状态文件")
+                progress!("从本地加载状态文件")
             } else if use_remote_state {
-                println!("从远端更新状态文件");
-                if !self.config.download_state.is_empty() {
+                progress!("从远端更新状态文件");
+                if let Some(backend) = &self.backend {
+                    backend.download_state(state_file.name(), state_file)?;
+                } else if !self.config.download_state.is_empty() {
                     self.execute_single_thread(&self.config.download_state, &self.variables)?;
                 }
             }
 
             if !state_file.exists() {
-                println!("未找到任何状态文件!使用默认的空状态!");
-                json::JsonValue::new_array()
+                progress!("未找到任何状态文件!使用默认的空状态!");
+                None
             } else {
-                json::parse(&state_file.read().unwrap()[..])
-                .expect(&format!("状态文件无法解析为Json格式: {}", state_file.path())[..])
+                Some(self.read_state_json(state_file)?)
             }
         } else {
-            println!("不加载任何状态文件!使用默认的空状态!");
-            json::JsonValue::new_array()
+            progress!("不加载任何状态文件!使用默认的空状态!");
+            None
         };
         
-        Ok(State::from_json_array(&state))
+        match state {
+            Some(state) => Ok(State::from_json(&state)?),
+            None => Ok(State::new(self.config.hash_algorithm)),
+        }
+    }
+
+    /// 读取状态文件并解析为Json，压缩方式由文件头决定，与state-compression的配置无关
+    fn read_state_json(&self, state_file: &File) -> AppResult<json::JsonValue> {
+        let invalid = |message: String| Box::new(Error::new(ErrorKind::InvalidData, format!("the state file cannot be parsed: {}: {}", state_file.path(), message)));
+
+        let data = StateCompression::decompress(&fs::read(state_file.path())?).map_err(|e| invalid(e.to_string()))?;
+        let text = String::from_utf8(data).map_err(|e| invalid(e.to_string()))?;
+
+        Ok(json::parse(&text).map_err(|e| invalid(e.to_string()))?)
+    }
+
+    /// acquire-lock和release-lock命令使用的变量
+    fn lock_variables(&self) -> VariableReplace {
+        let stale_after = self.config.lock.as_ref().and_then(|l| l.stale_after).map_or(0, |d| d.as_secs());
+
+        let mut vars = self.variables.to_owned();
+        vars.add("lock-owner", &self.lock_owner);
+        vars.add("lock-stale-after", &stale_after.to_string());
+        vars
+    }
+
+    /// 获取状态文件的锁: 先创建本地锁文件，再执行acquire-lock命令获取远端的锁<br/>
+    /// 锁被其它进程持有时每隔一段时间重试一次，超过lock.timeout时返回错误，未开启lock时不执行任何操作
+    fn acquire_state_lock(&self, state_file: &File) -> AppResult<()> {
+        let lock = match &self.config.lock {
+            Some(lock) => lock,
+            None => return Ok(()),
+        };
+
+        if self.state_lock.lock().unwrap().is_some() {
+            return Ok(());
+        }
+
+        let deadline = Instant::now() + lock.timeout;
+        let wait = || -> AppResult<bool> {
+            interrupt::check()?;
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Ok(false);
+            }
+            interrupt::sleep(remaining.min(LOCK_RETRY_INTERVAL));
+            interrupt::check()?;
+            Ok(true)
+        };
+
+        // 本地锁
+        let lock_file = StateLock::lock_file(state_file);
+        let mut waiting = false;
+        let mut local = loop {
+            if let Some(local) = StateLock::try_acquire(&lock_file, &self.lock_owner, lock.stale_after)? {
+                break local;
+            }
+
+            let holder = StateLock::holder(&lock_file);
+            if !waiting {
+                progress!("状态文件已被锁定({})，等待锁释放...", holder);
+                waiting = true;
+            }
+
+            if !wait()? {
+                return Err(Box::new(Error::new(ErrorKind::WouldBlock, format!(
+                    "the state is locked by {} (lock file: {}), remove the lock file if no other process is running", holder, lock_file.path()
+                ))));
+            }
+        };
+
+        // 运行期间定期刷新锁文件，避免对比或者上传的时间超过stale-after时被其它进程接管
+        if let Some(stale_after) = lock.stale_after {
+            local.keep_alive((stale_after / 3).max(Duration::from_secs(1)));
+        }
+        *self.state_lock.lock().unwrap() = Some(local);
+
+        // 远端锁，acquire-lock命令的返回码非0时视为锁被其它进程持有
+        if !self.config.acquire_lock.is_empty() {
+            let vars = self.lock_variables();
+            loop {
+                match self.execute_single_thread(&self.config.acquire_lock, &vars) {
+                    Ok(_) => break,
+                    Err(e) => {
+                        progress!("无法获取远端的锁，等待锁释放...");
+                        if !wait()? {
+                            return Err(Box::new(Error::new(ErrorKind::WouldBlock, format!("failed to acquire the remote lock: {}", e))));
+                        }
+                    },
+                }
+            }
+            self.remote_locked.store(true, Ordering::SeqCst);
+        }
+
+        Ok(())
+    }
+
+    /// 释放状态文件的锁，先执行release-lock命令再删除本地锁文件，没有持有锁时不执行任何操作
+    fn release_state_lock(&self) -> AppResult<()> {
+        let result = if self.remote_locked.swap(false, Ordering::SeqCst) && !self.config.release_lock.is_empty() {
+            self.execute_single_thread(&self.config.release_lock, &self.lock_variables())
+        } else {
+            Ok(())
+        };
+
+        // 即使release-lock命令失败，也要删除本地锁文件
+        drop(self.state_lock.lock().unwrap().take());
+
+        result
     }
 
-    pub fn save_state_file(&self, comparer: &FileComparer, state_file: &File, state: &State) -> AppResult<()> {
+    /// 第index个备份的状态文件，比如.state.json.1
+    fn state_backup_file(state_file: &File, index: u32) -> File {
+        File::new(&format!("{}.{}", state_file.path(), index))
+    }
+
+    /// 轮换状态文件的备份，当前的状态文件成为.1，原来的.1成为.2，以此类推，超出state-backups数量的备份会被删除
+    fn rotate_state_backups(&self, state_file: &File) -> AppResult<()> {
+        let backups = self.config.state_backups;
+        if backups == 0 || !state_file.is_file() {
+            return Ok(());
+        }
+
+        let oldest = App::state_backup_file(state_file, backups);
+        if oldest.exists() {
+            oldest.rm()?;
+        }
+
+        for index in (1..backups).rev() {
+            let backup = App::state_backup_file(state_file, index);
+            if backup.exists() {
+                fs::rename(backup.path(), App::state_backup_file(state_file, index + 1).path())?;
+            }
+        }
+
+        // 使用硬链接时不需要复制文件内容，之后的重命名不会影响到备份
+        let newest = App::state_backup_file(state_file, 1);
+        if fs::hard_link(state_file.path(), newest.path()).is_err() {
+            fs::copy(state_file.path(), newest.path())?;
+        }
+
+        Ok(())
+    }
+
+    /// 写入本地状态文件，本次运行第一次写入时，原来的状态文件会被轮换到备份中(仅开启use-local-state时)
+    fn write_state_file(&self, state_file: &File, contents: &[u8]) -> AppResult<()> {
+        state_file.parent()?.unwrap().mkdirs()?;
+
+        if self.config.use_local_state && !self.state_rotated.swap(true, Ordering::SeqCst) {
+            self.rotate_state_backups(state_file)?;
+        }
+
+        Ok(state_file.write_atomically(contents)?)
+    }
+
+    fn upload_state_file(&self, state_file: &File) -> AppResult<()> {
+        progress!("更新远端状态文件...");
+
+        if let Some(backend) = &self.backend {
+            backend.upload_state(state_file, state_file.name())?;
+        } else if !self.config.upload_state.is_empty() {
+            self.execute_single_thread(&self.config.upload_state, &self.variables)?;
+        }
+
+        Ok(())
+    }
+
+    /// 使用第index个备份替换当前的状态文件，开启use-remote-state时同时更新远端的状态文件<br/>
+    /// 当前的状态文件同样会被轮换到备份中，所以恢复操作本身也可以撤销
+    fn restore_state(&self, index: u32) -> AppResult<()> {
+        let state_file = self.get_state_file();
+        let backup = App::state_backup_file(&state_file, index);
+        if !backup.is_file() {
+            return Err(Box::new(Error::new(ErrorKind::NotFound, format!("the state backup does not exist: {}", backup.path()))));
+        }
+
+        self.acquire_state_lock(&state_file)?;
+
+        // 确认备份可以正常读取，备份在轮换之后会被移动，所以要先读取内容
+        State::from_json(&self.read_state_json(&backup)?)?;
+        let contents = fs::read(backup.path())?;
+
+        progress!("从备份恢复状态文件: {}", backup.path());
+        self.write_state_file(&state_file, &contents)?;
+
+        if self.config.use_remote_state {
+            self.upload_state_file(&state_file)?;
+        }
+
+        // 不保留本地状态文件
+        if !self.config.use_local_state {
+            state_file.rm()?;
+        }
+
+        Ok(())
+    }
+
+    /// 生成(压缩后的)状态文件内容
+    fn serialize_state(&self, state: &State) -> AppResult<Vec<u8>> {
+        let file_contents = state.to_json(&self.sourcedir.path());
+        let file_contents = if self.config.state_indent > 0 { 
+            file_contents.pretty(self.config.state_indent as u16)
+        } else { 
+            file_contents.dump() 
+        };
+
+        Ok(self.config.state_compression.compress(file_contents.as_bytes())?)
+    }
+
+    /// 在后台定期保存状态文件，直到所有操作都执行完毕(之后由save_state_file保存最终的状态)<br/>
+    /// 程序被强制结束时，下次运行可以从最后一个检查点继续，保存失败时只输出警告
+    fn save_checkpoints(&self, state_file: &File, state: &Mutex<Cell<State>>) {
+        let upload_state = self.config.use_remote_state && self.checkpoint.config().upload_state;
+
+        while self.checkpoint.wait() {
+            // 序列化时需要锁住状态，写入文件时不需要
+            let result = self.serialize_state(state.lock().unwrap().get_mut())
+                .and_then(|contents| self.write_state_file(state_file, &contents))
+                .and_then(|_| if upload_state { self.upload_state_file(state_file) } else { Ok(()) });
+
+            match result {
+                Ok(_) => progress!("已保存检查点"),
+                Err(e) => progress!("警告: 保存检查点失败: {}", e),
+            }
+        }
+    }
+
+    /// state_changed: 状态是否发生了变化，没有变化时不需要保存
+    pub fn save_state_file(&self, state_changed: bool, state_file: &File, state: &State) -> AppResult<()> {
         let update_local_state = self.config.use_local_state;
         let update_remote_state = self.config.use_remote_state;
 
-        if comparer.differences.has_differences() && (update_local_state || update_remote_state) {
+        if state_changed && (update_local_state || update_remote_state) {
             if update_local_state {
-                println!("更新本地状态文件...");
+                progress!("更新本地状态文件...");
             }
             
-            if state_file.exists() {
-                state_file.rm()?;
-            }
-            
-            let file_contents = state.to_json_array();
-            let file_contents = if self.config.state_indent > 0 { 
-                file_contents.pretty(self.config.state_indent as u16)
-            } else { 
-                file_contents.dump() 
-            };
-
-            state_file.parent()?.unwrap().mkdirs()?;
-            state_file.write(&file_contents)?;
+            self.write_state_file(state_file, &self.serialize_state(state)?)?;
 
             // 更新远端状态文件
             if update_remote_state {
-                println!("更新远端状态文件...");
-
-                if !self.config.upload_state.is_empty() {
-                    self.execute_single_thread(&self.config.upload_state, &self.variables)?;
-                }
+                self.upload_state_file(state_file)?;
             }
 
             // 不保留本地状态文件
@@ -222,64 +722,125 @@ impl App {
             }
         }
 
-        Ok(())
+        // 状态文件已经保存(或者不需要保存)，可以释放锁了
+        self.release_state_lock()
     }
 
     pub fn compare_files(&self, state: &State) -> AppResult<FileComparer> {
-        let compare_func = |remote: &FileData, local: &File, path: &str, fast_comparison: bool, hash_cache: &HashCache, debug_mode: bool| -> bool {
-            (fast_comparison && remote.modified == local.modified().map_or_else(|_e| 0, |v| v)) || 
-            remote.sha1 == hash_cache.get_hash(path, debug_mode)
+        let mtime_tolerance = self.config.mtime_tolerance;
+        let compare_func = move |remote: &FileData, local: &File, path: &str, comparison: Comparison, hash_cache: &HashCache, debug_mode: bool| -> bool {
+            comparison.is_unchanged(remote, local, path, hash_cache, mtime_tolerance, debug_mode)
         };
         
+        self.walk_filter.check_symlinks(&self.sourcedir)?;
+
         // 计算差异
-        let mut comparer = FileComparer::new(&self.sourcedir, Box::new(compare_func), &self.hash_cache, self.config.fast_comparison, &self.file_filter, self.options.debug);
-        println!("正在计算文件差异...");
+        let mut comparer = FileComparer::new(&self.sourcedir, Box::new(compare_func), &self.hash_cache, self.config.comparison, &self.file_filter, &self.walk_filter, self.options.debug);
+        progress!("正在计算文件差异...");
+        if state.hash_algorithm != self.config.hash_algorithm {
+            progress!("状态文件的hash算法({})与配置({})不一致，将使用{}进行对比并重新计算hash", 
+                state.hash_algorithm.name(), self.config.hash_algorithm.name(), state.hash_algorithm.name());
+        }
         comparer.compare(&self.sourcedir, &state)?;
 
+        // 未配置move-file(也没有使用内置后端)时，移动的文件按照先删除后上传处理
+        if self.backend.is_some() || !self.config.move_file.is_empty() {
+            comparer.find_moved_files(state)?;
+        }
+
         Ok(comparer)
     }
 
-    pub fn execute_operations(&self, comparer: &FileComparer, state: Arc<Mutex<Cell<State>>>) -> AppResult<()> {
-        let diff = &comparer.differences;
-
-        println!(
-            "旧文件: {}, 旧目录: {}, 新文件: {}, 新目录: {}", 
+    pub fn execute_operations(&self, diff: &Differences, state: Arc<Mutex<Cell<State>>>) -> AppResult<()> {
+        progress!(
+            "旧文件: {}, 旧目录: {}, 新文件: {}, 新目录: {}, 移动文件: {}", 
             diff.old_files.len(), diff.old_folders.len(),
             diff.new_files.len(), diff.new_folders.len(),
+            diff.moved_files.len(),
         );
 
+        // 比较文件期间收到中断信号时不再执行任何操作
+        interrupt::check()?;
+
         // 执行用户初始化指令
-        if comparer.differences.has_differences() && !self.config.start_up.is_empty() {
+        if diff.has_differences() && !self.config.start_up.is_empty() {
             self.execute_single_thread(&self.config.start_up, &self.variables)?;
         }
+
+        // 只删除本次运行创建的分块临时目录，执行前已经存在的目录(比如其它进程正在使用同一个计划)不会被删除
+        let created_chunk_dir = self.config.chunking.is_some() && !self.chunk_dir.exists();
+
+        let result = self.execute_file_operations(diff, state.clone());
+
+        // 删除本次运行的分块临时目录(包括出错时残留的临时文件)
+        if created_chunk_dir {
+            let _ = fs::remove_dir_all(&self.chunk_dir);
+        }
+
+        // 上传文件时会自动在状态里补上上级目录，创建失败的目录需要从状态里移除(包括其中的文件)，下次运行时重新创建
+        for dir in self.failures.paths(MAKE_DIR) {
+            state.lock().unwrap().get_mut().remove_file_or_dir(&dir);
+        }
+
+        // 执行用户清理指令(被中断时也会执行)
+        if diff.has_differences() && !self.config.clean_up.is_empty() && (result.is_ok() || interrupt::is_interrupted()) {
+            self.execute_single_thread(&self.config.clean_up, &self.variables)?;
+        }
+
+        result?;
+
+        progress!(
+            "旧文件: {}, 旧目录: {}, 新文件: {}, 新目录: {}, 移动文件: {}", 
+            diff.old_files.len(), diff.old_folders.len(),
+            diff.new_files.len(), diff.new_folders.len(),
+            diff.moved_files.len(),
+        );
+
+        // 报告keep-going模式下失败的操作
+        if !self.failures.is_empty() {
+            self.failures.print_summary();
+            return Err(Box::new(Error::other(format!("{} operations failed", self.failures.len()))));
+        }
+
+        Ok(())
+    }
+
+    /// 依次执行删除文件、创建目录、移动文件、删除目录、上传文件、修改权限、上传符号链接操作
+    fn execute_file_operations(&self, diff: &Differences, state: Arc<Mutex<Cell<State>>>) -> AppResult<()> {
+        // 删除文件时不会删除远端的分块，所以要在删除之前记录远端已有的分块
+        let known_chunks = Arc::new(KnownChunks::new(state.lock().unwrap().get_mut().chunk_hashes()));
         
         // 删除文件
         {
-            let filtered_old_files = diff.old_files
-                .iter()
-                .filter_map(|e| if self.config.overlay_mode && diff.new_files.contains(e) { None } else { Some(&e[..]) })
-                .collect::<Vec<&str>>();
+            let filtered_old_files = self.files_to_delete(diff);
             let total = filtered_old_files.len();
             let done = Arc::new(Mutex::new(0));
 
-            if !self.config.delete_file.is_empty() {
-                let varses = filtered_old_files.iter().map(|f| {
-                    let mut vars = self.variables.to_owned();
-                    vars.add("path", f);
-                    vars.add("path_", &f.replace("/", "\\"));
-                    vars
-                }).collect::<Vec<VariableReplace>>();
+            let job: Option<Arc<Job>> = match &self.backend {
+                Some(backend) => {
+                    let backend = backend.clone();
+                    Some(Arc::new(move |vars: &VariableReplace| backend.delete_file(vars.variables.get("path").unwrap())))
+                },
+                None if !self.config.delete_file.is_empty() => Some(self.command_job(&self.config.delete_file, &self.config.delete_file_retry)),
+                None => None,
+            };
+            let job = self.routed_job(RouteAction::DeleteFile, job);
+
+            if let Some(job) = job {
+                let tasks = filtered_old_files.iter()
+                    .map(|f| (f.to_string(), self.path_variables(f)))
+                    .collect::<Vec<(String, VariableReplace)>>();
 
                 let state = state.clone();
 
                 self.execute_multiple_thread(
-                    &self.config.delete_file, 
-                    self.config.threads as usize, 
-                    &varses, 
+                    "删除文件",
+                    &tasks, 
+                    job,
                     Box::new(move |vars| {
                         let mut done = done.lock().unwrap();
                         *done += 1;
-                        println!("删除文件({}/{}): {}", done, total, vars.variables.get("path").unwrap());
+                        progress!("删除文件({}/{}): {}", done, total, vars.variables.get("path").unwrap());
                     }),
                     Box::new(move |vars| {
                         let path = vars.variables.get("path").unwrap();
@@ -291,7 +852,7 @@ impl App {
                     let mut done = done.lock().unwrap();
                     *done += 1;
                     state.lock().unwrap().get_mut().remove_file_or_dir(&f);
-                    println!("删除文件({}/{}): {}", done, total, f);
+                    progress!("删除文件({}/{}): {}", done, total, f);
                 }
             }
 
@@ -303,43 +864,100 @@ impl App {
             }
         }
 
-        // 删除目录
+        // 创建目录
         {
-            let total = &diff.old_folders.len();
+            let total = &diff.new_folders.len();
             let mut done = 0;
-            for f in &diff.old_folders {
-                let mut vars = self.variables.to_owned();
-                vars.add("path", f);
-                vars.add("path_", &f.replace("/", "\\"));
+            for f in &diff.new_folders {
+                interrupt::check()?;
+                let vars = self.path_variables(f);
 
                 done += 1;
-                println!("删除目录({}/{}): {}", done, total, f);
+                progress!("新目录({}/{}): {}", done, total, f);
+
+                let result = match &self.backend {
+                    Some(backend) => backend.make_dir(f).map_err(|e| e.into()),
+                    None if !self.config.upload_dir.is_empty() => self.execute_single_thread_with_retry(&self.config.upload_dir, &vars, &self.config.upload_dir_retry),
+                    None => Ok(()),
+                };
 
-                if !self.config.delete_dir.is_empty() {
-                    self.execute_single_thread(&self.config.delete_dir, &vars)?;
+                if !self.check_result(MAKE_DIR, f, result)? {
+                    continue;
                 }
 
-                state.lock().unwrap().get_mut().remove_file_or_dir(f);
+                state.lock().unwrap().get_mut().make_dir(f);
+                self.checkpoint.record();
             }
         }
 
-        // 创建目录
+        // 移动文件(需要在创建目录之后，删除目录之前进行)
+        if !diff.moved_files.is_empty() {
+            let total = diff.moved_files.len();
+            let done = Arc::new(Mutex::new(0));
+
+            let tasks = diff.moved_files.iter()
+                .map(|(from, to)| (format!("{} -> {}", from, to), self.move_variables(from, to)))
+                .collect::<Vec<(String, VariableReplace)>>();
+
+            let job: Arc<Job> = match &self.backend {
+                Some(backend) => {
+                    let backend = backend.clone();
+                    Arc::new(move |vars: &VariableReplace| backend.move_file(vars.variables.get("from").unwrap(), vars.variables.get("to").unwrap()))
+                },
+                None => self.command_job(&self.config.move_file, &self.config.move_file_retry),
+            };
+
+            let sourcedir = self.sourcedir.to_owned();
+            let hash_cache = self.hash_cache.clone();
+            let debug = self.options.debug;
+            let state = state.clone();
+
+            self.execute_multiple_thread(
+                "移动文件",
+                &tasks, 
+                job,
+                Box::new(move |vars| {
+                    let mut done = done.lock().unwrap();
+                    *done += 1;
+                    progress!("移动文件({}/{}): {} -> {}", done, total, vars.variables.get("from").unwrap(), vars.variables.get("to").unwrap());
+                }),
+                Box::new(move |vars| {
+                    let from = vars.variables.get("from").unwrap();
+                    let to = vars.variables.get("to").unwrap();
+                    let mut state = state.lock().unwrap();
+                    let state = state.get_mut();
+                    // 移动后的文件内容不变，远端的分块仍然可以复用
+                    let chunks = state.files.get_file(from).and_then(|f| f.as_file()).and_then(|f| f.chunks.clone());
+                    state.remove_file_or_dir(from);
+                    state.add_file(to, &sourcedir, &hash_cache, debug);
+                    state.set_chunks(to, chunks);
+                })
+            )?;
+        }
+
+        // 删除目录
         {
-            let total = &diff.new_folders.len();
+            let total = &diff.old_folders.len();
             let mut done = 0;
-            for f in &diff.new_folders {
-                let mut vars = self.variables.to_owned();
-                vars.add("path", f);
-                vars.add("path_", &f.replace("/", "\\"));
+            for f in &diff.old_folders {
+                interrupt::check()?;
+                let vars = self.path_variables(f);
 
                 done += 1;
-                println!("新目录({}/{}): {}", done, total, f);
+                progress!("删除目录({}/{}): {}", done, total, f);
+
+                let result = match &self.backend {
+                    Some(backend) => backend.delete_dir(f).map_err(|e| e.into()),
+                    None if !self.config.delete_dir.is_empty() => self.execute_single_thread_with_retry(&self.config.delete_dir, &vars, &self.config.delete_dir_retry),
+                    None => Ok(()),
+                };
 
-                if !self.config.upload_dir.is_empty() {
-                    self.execute_single_thread(&self.config.upload_dir, &vars)?;
+                if !self.check_result("删除目录", f, result)? {
+                    continue;
                 }
 
-                state.lock().unwrap().get_mut().make_dir(f);
+                state.lock().unwrap().get_mut().remove_file_or_dir(f);
+                self.checkpoint.record();
             }
         }
 
@@ -348,13 +966,26 @@ impl App {
             let total = diff.new_files.len();
             let done = Arc::new(Mutex::new(0));
     
-            if !self.config.upload_file.is_empty() {
-                let varses = diff.new_files.iter().map(|f| {
-                    let mut vars = self.variables.to_owned();
-                    vars.add("path", f);
-                    vars.add("path_", &f.replace("/", "\\"));
-                    vars
-                }).collect::<Vec<VariableReplace>>();
+            let job: Option<Arc<Job>> = match &self.backend {
+                Some(backend) => {
+                    let backend = backend.clone();
+                    let sourcedir = self.sourcedir.to_owned();
+                    Some(Arc::new(move |vars: &VariableReplace| {
+                        let path = vars.variables.get("path").unwrap();
+                        backend.upload_file(&sourcedir.append(path)?, path)
+                    }))
+                },
+                None if !self.config.upload_file.is_empty() => Some(self.command_job(&self.config.upload_file, &self.config.upload_file_retry)),
+                None => None,
+            };
+            let uploaded = Arc::new(Mutex::new(HashMap::new()));
+            let job = self.chunked_job(job, known_chunks, uploaded.clone());
+            let job = self.routed_job(RouteAction::UploadFile, job);
+
+            if let Some(job) = job {
+                let tasks = diff.new_files.iter()
+                    .map(|f| Ok((f.to_owned(), self.file_variables(f)?)))
+                    .collect::<AppResult<Vec<(String, VariableReplace)>>>()?;
     
                 let sourcedir = self.sourcedir.to_owned();
                 let hash_cache = self.hash_cache.clone();
@@ -362,62 +993,331 @@ impl App {
                 let state = state.clone();
     
                 self.execute_multiple_thread(
-                    &self.config.upload_file, 
-                    self.config.threads as usize, 
-                    &varses, 
+                    "新文件",
+                    &tasks, 
+                    job,
                     Box::new(move |vars| {
                         let mut done = done.lock().unwrap();
                         *done += 1;
-                        println!("新文件({}/{}): {}", done, total, vars.variables.get("path").unwrap());
+                        progress!("新文件({}/{}): {}", done, total, vars.variables.get("path").unwrap());
                     }),
                     Box::new(move |vars| {
                         let path = vars.variables.get("path").unwrap();
-                        state.lock().unwrap().get_mut().add_file(&path, &sourcedir, &hash_cache, debug);
+                        let mut state = state.lock().unwrap();
+                        let state = state.get_mut();
+                        state.add_file(path, &sourcedir, &hash_cache, debug);
+                        // 分块上传的文件记录分块列表，整体上传的文件不再有分块
+                        state.set_chunks(path, uploaded.lock().unwrap().remove(path));
                     })
                 )?;
             } else {
                 for f in &diff.new_files {
                     let mut done = done.lock().unwrap();
                     *done += 1;
-                    println!("新文件({}/{}): {}", done, total, f);
+                    progress!("新文件({}/{}): {}", done, total, f);
                     state.lock().unwrap().get_mut().add_file(f, &self.sourcedir, &self.hash_cache, self.options.debug);
                 }
             }
         }
 
-        // 执行用户清理指令
-        if comparer.differences.has_differences() && !self.config.clean_up.is_empty() {
-            self.execute_single_thread(&self.config.clean_up, &self.variables)?;
+        // 修改权限(内容没有变化的文件)
+        if !diff.chmod_files.is_empty() {
+            let total = diff.chmod_files.len();
+            let done = Arc::new(Mutex::new(0));
+
+            let tasks = diff.chmod_files.iter()
+                .map(|f| Ok((f.to_owned(), self.file_variables(f)?)))
+                .collect::<AppResult<Vec<(String, VariableReplace)>>>()?;
+
+            let job: Arc<Job> = match &self.backend {
+                Some(backend) => {
+                    let backend = backend.clone();
+                    Arc::new(move |vars: &VariableReplace| {
+                        let mode = u32::from_str_radix(vars.variables.get("mode").unwrap(), 8).unwrap();
+                        backend.chmod_file(vars.variables.get("path").unwrap(), mode)
+                    })
+                },
+                None if !self.config.chmod_file.is_empty() => self.command_job(&self.config.chmod_file, &self.config.chmod_file_retry),
+                None => Arc::new(|_: &VariableReplace| Ok(())),
+            };
+
+            let sourcedir = self.sourcedir.to_owned();
+            let hash_cache = self.hash_cache.clone();
+            let debug = self.options.debug;
+            let state = state.clone();
+
+            self.execute_multiple_thread(
+                "修改权限",
+                &tasks,
+                job,
+                Box::new(move |vars| {
+                    let mut done = done.lock().unwrap();
+                    *done += 1;
+                    progress!("修改权限({}/{}): {} ({})", done, total, vars.variables.get("path").unwrap(), vars.variables.get("mode").unwrap());
+                }),
+                Box::new(move |vars| {
+                    let path = vars.variables.get("path").unwrap();
+                    state.lock().unwrap().get_mut().add_file(path, &sourcedir, &hash_cache, debug);
+                })
+            )?;
         }
 
-        println!(
-            "旧文件: {}, 旧目录: {}, 新文件: {}, 新目录: {}", 
-            diff.old_files.len(), diff.old_folders.len(),
-            diff.new_files.len(), diff.new_folders.len(),
-        );
+        // 上传符号链接(仅preserve模式)
+        if !diff.new_symlinks.is_empty() {
+            let total = diff.new_symlinks.len();
+            let done = Arc::new(Mutex::new(0));
+
+            let tasks = diff.new_symlinks.iter()
+                .map(|f| Ok((f.to_owned(), self.symlink_variables(f)?)))
+                .collect::<AppResult<Vec<(String, VariableReplace)>>>()?;
+
+            let job: Arc<Job> = match &self.backend {
+                Some(backend) => {
+                    let backend = backend.clone();
+                    Arc::new(move |vars: &VariableReplace| backend.upload_symlink(vars.variables.get("link-target").unwrap(), vars.variables.get("path").unwrap()))
+                },
+                None if !self.config.upload_symlink.is_empty() => self.command_job(&self.config.upload_symlink, &self.config.upload_symlink_retry),
+                None => Arc::new(|_: &VariableReplace| Ok(())),
+            };
+
+            let state = state.clone();
+
+            self.execute_multiple_thread(
+                "符号链接",
+                &tasks,
+                job,
+                Box::new(move |vars| {
+                    let mut done = done.lock().unwrap();
+                    *done += 1;
+                    progress!("符号链接({}/{}): {} -> {}", done, total, vars.variables.get("path").unwrap(), vars.variables.get("link-target").unwrap());
+                }),
+                Box::new(move |vars| {
+                    let path = vars.variables.get("path").unwrap();
+                    let link_target = vars.variables.get("link-target").unwrap();
+                    state.lock().unwrap().get_mut().add_symlink(path, link_target);
+                })
+            )?;
+        }
 
         Ok(())
     }
 
+    /// 展开命令行中的变量，得到实际会执行的命令
+    fn expand_commands(&self, commands: &Vec<Vec<String>>, vars: &VariableReplace) -> AppResult<Vec<Vec<String>>> {
+        let mut expanded = Vec::new();
+        for step in commands {
+            expanded.push(SubprocessTask::from_command_line(step, &self.workdir, vars, None)?.raw_divided);
+        }
+
+        Ok(expanded)
+    }
+
+    /// 分块上传会执行的命令(与chunked_job的执行过程一致)，未启用分块上传或者文件小于chunking.min-file-size时返回None<br/>
+    /// known_chunks: 远端已有的分块以及计划中已经上传的分块
+    fn chunked_commands(&self, path: &str, known_chunks: &mut HashSet<String>) -> AppResult<Option<Vec<Vec<String>>>> {
+        let chunking = match &self.config.chunking {
+            Some(chunking) => chunking,
+            None => return Ok(None),
+        };
+
+        let file = self.sourcedir.append(path)?;
+        if file.length()? < chunking.min_file_size {
+            return Ok(None);
+        }
+
+        let mut vars = self.file_variables(path)?;
+        let mut commands = Vec::new();
+
+        let mut offset = 0;
+        for chunk in chunking.split(&file, self.hash_cache.algorithm())? {
+            if known_chunks.insert(chunk.hash.to_owned()) {
+                let chunk_file = chunk_file(&self.chunk_dir, &chunk.hash);
+                commands.extend(self.expand_commands(&self.config.upload_chunk, &chunk_variables(&vars, &chunk_file, offset, &chunk))?);
+            }
+            offset += chunk.length;
+        }
+
+        vars.add("manifest", &manifest_file(&self.chunk_dir, path).path());
+        commands.extend(self.expand_commands(&self.config.upload_manifest, &vars)?);
+
+        Ok(Some(commands))
+    }
+
+    /// 按照execute_operations的执行顺序生成计划，不会执行任何操作
+    pub fn make_plan(&self, diff: &Differences, state: &State) -> AppResult<Plan> {
+        let mut plan = Plan::new(&self.config.backend, self.hash_cache.algorithm());
+        if self.config.chunking.is_some() {
+            plan.temp_dir = Some(self.chunk_dir.to_string_lossy().to_string());
+        }
+
+        let local_file = |path: &str| -> AppResult<(Option<u64>, Option<String>)> {
+            let length = self.sourcedir.append(path)?.length()?;
+            Ok((Some(length), Some(self.hash_cache.get_hash(path, self.options.debug))))
+        };
+
+        // 使用内置后端时，文件操作不会执行任何命令
+        let file_commands = |commands: &Vec<Vec<String>>, vars: &VariableReplace| -> AppResult<Vec<Vec<String>>> {
+            if self.backend.is_some() { Ok(Vec::new()) } else { self.expand_commands(commands, vars) }
+        };
+
+        // 匹配路由的文件使用路由中的命令
+        let routed_commands = |action: RouteAction, path: &str, commands: &Vec<Vec<String>>| -> AppResult<Vec<Vec<String>>> {
+            let vars = match action {
+                RouteAction::UploadFile => self.file_variables(path)?,
+                RouteAction::DeleteFile => self.path_variables(path),
+            };
+            match find_route(&self.routes, action, path) {
+                Some(i) => self.expand_commands(self.routes[i].commands(action).0, &vars),
+                None => file_commands(commands, &vars),
+            }
+        };
+
+        let operation = |action: &str, path: &str, commands: Vec<Vec<String>>| PlannedOperation {
+            action: action.to_owned(),
+            path: path.to_owned(),
+            from: None,
+            size: None,
+            hash: None,
+            link_target: None,
+            mode: None,
+            commands,
+        };
+
+        let has_differences = diff.has_differences();
+
+        if has_differences && !self.config.start_up.is_empty() {
+            plan.operations.push(operation("start-up", "", self.expand_commands(&self.config.start_up, &self.variables)?));
+        }
+
+        for f in self.files_to_delete(diff) {
+            let mut op = operation("delete-file", f, routed_commands(RouteAction::DeleteFile, f, &self.config.delete_file)?);
+            if let Some(old) = state.files.get_file(f).and_then(|v| v.as_file()).filter(|v| v.link_target.is_none()) {
+                op.size = Some(old.length);
+                op.hash = Some(old.hash.to_owned());
+            }
+            plan.operations.push(op);
+        }
+
+        for f in &diff.new_folders {
+            plan.operations.push(operation("make-dir", f, file_commands(&self.config.upload_dir, &self.path_variables(f))?));
+        }
+
+        for (from, to) in &diff.moved_files {
+            let mut op = operation("move-file", to, file_commands(&self.config.move_file, &self.move_variables(from, to))?);
+            op.from = Some(from.to_owned());
+            (op.size, op.hash) = local_file(to)?;
+            plan.operations.push(op);
+        }
+
+        for f in &diff.old_folders {
+            plan.operations.push(operation("delete-dir", f, file_commands(&self.config.delete_dir, &self.path_variables(f))?));
+        }
+
+        let mut known_chunks = state.chunk_hashes();
+        for f in &diff.new_files {
+            // 匹配路由的文件不使用分块上传
+            let chunked = match find_route(&self.routes, RouteAction::UploadFile, f) {
+                Some(_) => None,
+                None => self.chunked_commands(f, &mut known_chunks)?,
+            };
+            let commands = match chunked {
+                Some(commands) => commands,
+                None => routed_commands(RouteAction::UploadFile, f, &self.config.upload_file)?,
+            };
+            let mut op = operation("upload-file", f, commands);
+            (op.size, op.hash) = local_file(f)?;
+            plan.operations.push(op);
+        }
+
+        for f in &diff.chmod_files {
+            let vars = self.file_variables(f)?;
+            let mut op = operation("chmod-file", f, file_commands(&self.config.chmod_file, &vars)?);
+            op.mode = vars.variables.get("mode").map(|v| v.to_owned());
+            plan.operations.push(op);
+        }
+
+        for f in &diff.new_symlinks {
+            let vars = self.symlink_variables(f)?;
+            let mut op = operation("upload-symlink", f, file_commands(&self.config.upload_symlink, &vars)?);
+            op.link_target = vars.variables.get("link-target").map(|v| v.to_owned());
+            plan.operations.push(op);
+        }
+
+        if has_differences && !self.config.clean_up.is_empty() {
+            plan.operations.push(operation("clean-up", "", self.expand_commands(&self.config.clean_up, &self.variables)?));
+        }
+
+        Ok(plan)
+    }
+
+    /// 读取计划文件，并检查计划与当前的源文件、状态和配置是否仍然一致，返回计划中的文件差异
+    fn load_plan(&mut self, plan_file: &File, state: &State) -> AppResult<Differences> {
+        let plan = Plan::from_json(&json::parse(&plan_file.read()?)?)?;
+
+        // 计划中的命令行包含了分块和清单的临时文件路径，执行时需要使用同一个目录
+        // 执行结束后会删除这个目录，所以只接受run_temp_dir()格式的目录
+        if let Some(temp_dir) = &plan.temp_dir {
+            let temp_dir = PathBuf::from(temp_dir);
+            if !is_run_temp_dir(&temp_dir) {
+                return Err(Box::new(Error::new(ErrorKind::InvalidData, format!("the temp-dir in the plan is not a chunk temp directory: {}", temp_dir.display()))));
+            }
+            self.chunk_dir = temp_dir;
+        }
+
+        let out_of_date = |message: String| Box::new(Error::new(ErrorKind::InvalidData, format!("the plan is out of date: {}", message)));
+
+        if state.hash_algorithm != self.config.hash_algorithm {
+            return Err(out_of_date(format!("the state file uses {} instead of {}", state.hash_algorithm.name(), self.config.hash_algorithm.name())));
+        }
+
+        // 使用相同的差异重新生成计划，文件的大小、hash以及命令行都需要和计划文件中的完全一致
+        let diff = plan.to_differences();
+        let current = self.make_plan(&diff, state)?;
+
+        if plan.backend != current.backend || plan.hash_algorithm != current.hash_algorithm {
+            return Err(out_of_date(format!(
+                "the plan was created for backend '{}' with {}, but the config uses backend '{}' with {}",
+                plan.backend, plan.hash_algorithm.name(), current.backend, current.hash_algorithm.name()
+            )));
+        }
+
+        if plan.operations.len() != current.operations.len() {
+            return Err(out_of_date(format!("expected {} operations, found {}", plan.operations.len(), current.operations.len())));
+        }
+
+        for (expected, actual) in plan.operations.iter().zip(current.operations.iter()) {
+            if expected != actual {
+                return Err(out_of_date(format!("\nexpected: {:?}\nactual: {:?}", expected, actual)));
+            }
+        }
+
+        Ok(diff)
+    }
+
     fn test_filter(&self) -> AppResult<()> {
-        fn walk(directory: &File, base: &File, filter: &RuleFilter) -> AppResult<()> {
+        fn walk(directory: &File, base: &File, filter: &RuleFilter, walk_filter: &WalkFilter) -> AppResult<()> {
             for f in directory.files()? {
                 let f = f?;
+                if walk_filter.is_ignored(&f) {
+                    continue;
+                }
+
                 let relative_path = f.relativized_by(base);
-                let matched = filter.test_all(&relative_path, true);
+                let matched = filter.test_path(&relative_path, f.is_dir());
                 if matched {
-                    println!("matched: {}", relative_path);
+                    progress!("matched: {}", relative_path);
                 }
 
-                if f.is_dir() {
-                    walk(&f, base, filter)?;
+                if f.is_dir() && !walk_filter.preserves(&f) {
+                    walk(&f, base, filter, walk_filter)?;
                 }
             }
 
             Ok(())
         }
 
-        walk(&self.sourcedir, &self.sourcedir, &self.file_filter)?;
+        self.walk_filter.check_symlinks(&self.sourcedir)?;
+        walk(&self.sourcedir, &self.sourcedir, &self.file_filter, &self.walk_filter)?;
 
         Ok(())
     }
@@ -428,22 +1328,105 @@ impl App {
             return Ok(());
         }
 
+        let result = match self.options.restore_state {
+            Some(index) => self.restore_state(index),
+            None => self.run(),
+        };
+
+        // 出错时同样需要释放状态文件的锁
+        let released = self.release_state_lock();
+        result?;
+        released
+    }
+
+    fn run(&mut self) -> AppResult<()> {
+        // 计划可能会输出到标准输出，进度信息不能混在其中
+        if self.options.plan.is_some() {
+            utils::set_progress_to_stderr(true);
+        }
+
         let state_file = self.get_state_file();
+        let hash_cache_file = self.get_hash_cache_file();
+
+        // paranoid模式下不信任缓存的hash，但仍然会更新缓存文件
+        if let Some(hash_cache_file) = &hash_cache_file {
+            if self.config.comparison != Comparison::Paranoid {
+                self.hash_cache.load_from_file(hash_cache_file, self.options.debug)?;
+            }
+        }
+
         let state = Arc::new(Mutex::new(Cell::new(self.load_state_from_file(&state_file)?)));
-        let comparer = self.compare_files(state.lock().unwrap().get_mut())?;
 
-        // 执行远端读写操作
-        let result = self.execute_operations(&comparer, state.clone());
+        // 执行计划文件时不再重新对比文件
+        let (differences, rehash_files, unrecorded_modes) = match self.options.apply_plan.clone() {
+            Some(plan_file) => {
+                progress!("正在检查计划文件...");
+                (self.load_plan(&File::new(&plan_file), state.lock().unwrap().get_mut())?, None, Vec::new())
+            },
+            None => {
+                let comparer = self.compare_files(state.lock().unwrap().get_mut())?;
+                (comparer.differences, comparer.rehash_files, comparer.unrecorded_modes)
+            },
+        };
+
+        // 使用新的hash算法更新内容未变化的文件
+        if let Some(rehash_files) = &rehash_files {
+            state.lock().unwrap().get_mut().rehash(rehash_files, &self.hash_cache, self.options.debug);
+        }
+
+        // 旧版本的状态文件中没有记录权限，直接记录当前的权限
+        state.lock().unwrap().get_mut().set_modes(&unrecorded_modes);
+
+        // 只输出计划，不执行任何操作，也不更新状态文件
+        if let Some(format) = &self.options.plan {
+            let plan = self.make_plan(&differences, state.lock().unwrap().get_mut())?.render(format);
+
+            match &self.options.plan_output {
+                Some(output) => File::new(output).write_atomically(plan.as_bytes())?,
+                None => println!("{}", plan.trim_end()),
+            }
+
+            return Ok(());
+        }
+
+        // 执行远端读写操作，同时在后台定期保存检查点
+        let result = thread::scope(|scope| {
+            if self.checkpoint.config().is_enabled() && (self.config.use_local_state || self.config.use_remote_state) {
+                scope.spawn(|| self.save_checkpoints(&state_file, &state));
+            }
+
+            let result = self.execute_operations(&differences, state.clone());
+            self.checkpoint.finish();
+            result
+        });
         
-        if result.is_err() {
-            println!("更新状态时出现错误，保存状态文件");
+        if interrupt::is_interrupted() {
+            progress!("已中断，保存已完成的部分的状态");
+        } else if result.is_err() {
+            progress!("更新状态时出现错误，保存状态文件");
         }
 
         // 更新状态文件
-        self.save_state_file(&comparer, &state_file, state.lock().unwrap().get_mut())?;
+        let state_changed = differences.has_differences() || rehash_files.is_some() || !unrecorded_modes.is_empty();
+        self.save_state_file(state_changed, &state_file, state.lock().unwrap().get_mut())?;
+
+        // 更新hash缓存文件
+        if let Some(hash_cache_file) = &hash_cache_file {
+            self.hash_cache.save_to_file(hash_cache_file)?;
+        }
 
         result?;
 
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+/// 上传单个分块使用的变量，$chunk-file为分块的临时文件
+fn chunk_variables(vars: &VariableReplace, chunk_file: &File, offset: u64, chunk: &Chunk) -> VariableReplace {
+    let mut vars = vars.clone();
+    vars.add("chunk-hash", &chunk.hash);
+    vars.add("chunk-file", &chunk_file.path());
+    vars.add("chunk-offset", &offset.to_string());
+    vars.add("chunk-length", &chunk.length.to_string());
+    vars
+}
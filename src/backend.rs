@@ -0,0 +1,75 @@
+use std::io::Error;
+use std::io::ErrorKind;
+use std::io::Result;
+use std::sync::Arc;
+
+use crate::app_config::AppConfig;
+use crate::file::File;
+use crate::local_backend::LocalBackend;
+use crate::s3_backend::S3Backend;
+use crate::sftp_backend::SftpBackend;
+
+/// 内置的远端存储后端，配置后文件操作由程序直接完成，不再需要为每个文件启动外部命令<br/>
+/// 所有路径都是相对于源目录的路径，使用/作为分隔符
+pub trait Backend: Send + Sync {
+    /// 上传(覆盖)一个文件，远端的父目录不存在时会被自动创建
+    fn upload_file(&self, local: &File, path: &str) -> Result<()>;
+
+    /// 删除一个文件，文件不存在时视为成功
+    fn delete_file(&self, path: &str) -> Result<()>;
+
+    /// 创建一个目录(包括所有父目录)
+    fn make_dir(&self, path: &str) -> Result<()>;
+
+    /// 删除一个目录(包括其中剩余的内容)，目录不存在时视为成功
+    fn delete_dir(&self, path: &str) -> Result<()>;
+
+    /// 移动一个文件，目标文件已存在时会被覆盖
+    fn move_file(&self, from: &str, to: &str) -> Result<()>;
+
+    /// 修改文件的权限位(比如0o755)，不支持权限的后端直接忽略
+    fn chmod_file(&self, path: &str, mode: u32) -> Result<()>;
+
+    /// 创建(覆盖)一个指向target的符号链接，仅在symlinks为preserve时使用
+    fn upload_symlink(&self, target: &str, path: &str) -> Result<()> {
+        Err(Error::new(ErrorKind::Unsupported, format!("the backend does not support symlinks: {} -> {}", path, target)))
+    }
+
+    /// 上传状态文件，name为状态文件的文件名
+    fn upload_state(&self, local: &File, name: &str) -> Result<()>;
+
+    /// 下载状态文件到local，远端没有状态文件时返回false
+    fn download_state(&self, name: &str, local: &File) -> Result<bool>;
+}
+
+/// 根据配置文件的backend选项创建对应的后端，未配置时返回None(使用commands下的命令)
+pub fn from_config(config: &AppConfig) -> Result<Option<Arc<dyn Backend>>> {
+    match config.backend.as_str() {
+        "" => Ok(None),
+        "local" => {
+            if config.target_dir.is_empty() {
+                return Err(Error::new(ErrorKind::InvalidInput, "the config field 'target-dir' must be present when using the local backend"));
+            }
+
+            let target_dir = File::new(&config.target_dir);
+            target_dir.mkdirs()?;
+
+            Ok(Some(Arc::new(LocalBackend::new(&target_dir))))
+        },
+        "s3" => {
+            if config.s3.endpoint.is_empty() || config.s3.bucket.is_empty() {
+                return Err(Error::new(ErrorKind::InvalidInput, "the config field 's3.endpoint' and 's3.bucket' must be present when using the s3 backend"));
+            }
+
+            Ok(Some(Arc::new(S3Backend::new(&config.s3)?)))
+        },
+        "sftp" => {
+            if config.sftp.host.is_empty() || config.sftp.username.is_empty() || config.target_dir.is_empty() {
+                return Err(Error::new(ErrorKind::InvalidInput, "the config field 'sftp.host', 'sftp.username' and 'target-dir' must be present when using the sftp backend"));
+            }
+
+            Ok(Some(Arc::new(SftpBackend::new(&config.sftp, &config.target_dir)?)))
+        },
+        other => Err(Error::new(ErrorKind::InvalidInput, format!("unsupported backend: {}", other))),
+    }
+}
@@ -3,6 +3,8 @@ use std::cell::UnsafeCell;
 use std::error::Error;
 use std::sync::Arc;
 use std::sync::Mutex;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
 use std::sync::mpsc;
 use std::sync::mpsc::Receiver;
 use std::sync::mpsc::SyncSender;
@@ -10,6 +12,7 @@ use std::thread;
 use std::thread::JoinHandle;
 
 type Task = Box<dyn (FnOnce() -> Result<(), Box<dyn Error + Send>>) + Send>;
+type TaskErrors = Vec<Box<dyn Error + Send>>;
 
 pub enum WorkerMessage {
     Task(Task),
@@ -23,6 +26,7 @@ pub struct Worker {
     pub thread: Option<JoinHandle<()>>,
     pub exited_flag: bool,
     pub busy: bool,
+    pub cancelled: Arc<AtomicBool>,
     pub on_error: Box<dyn Fn(Box<dyn std::error::Error + Send>) + Send>,
 }
 
@@ -31,6 +35,7 @@ impl Worker {
         id: u32, 
         sender: SyncSender<WorkerMessage>, 
         receiver: Arc<Mutex<Receiver<WorkerMessage>>>,
+        cancelled: Arc<AtomicBool>,
         on_error: Box<dyn Fn(Box<dyn std::error::Error + Send>) + Send>,
     ) -> Arc<UnsafeCell<Worker>> {
         let worker = Arc::new(UnsafeCell::new(Worker {
@@ -40,6 +45,7 @@ impl Worker {
             exited_flag: false,
             thread: None,
             busy: false,
+            cancelled,
             on_error,
         }));
 
@@ -58,11 +64,16 @@ impl Worker {
             
             match msg {
                 WorkerMessage::Task(task) => {
+                    // 线程池已被取消，丢弃剩余的任务
+                    if self.cancelled.load(Ordering::SeqCst) {
+                        continue;
+                    }
+
                     self.busy = true;
                     let result = task();
                     if result.is_err() {
+                        // 任务失败后worker继续处理后续任务，错误由线程池统一收集
                         (self.on_error)(result.err().unwrap());
-                        return;
                     }
                     self.busy = false;
                 }
@@ -96,23 +107,31 @@ pub struct BlockingThreadPool {
     workers: Vec<Arc<UnsafeCell<Worker>>>,
     sender: mpsc::SyncSender<WorkerMessage>,
     is_terminated: bool,
-    error: Arc<Mutex<Cell<Option<Box<dyn std::error::Error + Send>>>>>
+    cancelled: Arc<AtomicBool>,
+    errors: Arc<Mutex<Cell<TaskErrors>>>
 }
 
 impl BlockingThreadPool {
-    pub fn new(size: usize) -> BlockingThreadPool {
+    /// size: 线程数量<br/>
+    /// fail_fast: 为true时，首个任务失败后其余还未开始执行的任务都会被丢弃
+    pub fn new(size: usize, fail_fast: bool) -> BlockingThreadPool {
         assert!(size > 0);
 
         let (sender, receiver) = mpsc::sync_channel(0);
         let workers = Vec::with_capacity(size);
         let receiver = Arc::new(Mutex::new(receiver));
+        let cancelled = Arc::new(AtomicBool::new(false));
 
-        let mut ins = BlockingThreadPool { workers, sender, is_terminated: false, error: Arc::new(Mutex::new(Cell::new(None))) };
+        let mut ins = BlockingThreadPool { workers, sender, is_terminated: false, cancelled, errors: Arc::new(Mutex::new(Cell::new(Vec::new()))) };
         
         for id in 0..size {
-            let ins_copy = ins.error.clone();
-            ins.workers.push(Worker::new(id as u32, ins.sender.clone(), receiver.clone(), Box::new(move |err| {
-                *ins_copy.lock().unwrap().get_mut() = Some(err);
+            let ins_copy = ins.errors.clone();
+            let cancelled = ins.cancelled.clone();
+            ins.workers.push(Worker::new(id as u32, ins.sender.clone(), receiver.clone(), ins.cancelled.clone(), Box::new(move |err| {
+                ins_copy.lock().unwrap().get_mut().push(err);
+                if fail_fast {
+                    cancelled.store(true, Ordering::SeqCst);
+                }
             })));
         }
         
@@ -127,7 +146,13 @@ impl BlockingThreadPool {
         self.sender.send(WorkerMessage::Task(Box::new(fun))).unwrap();
     }
 
-    pub fn close_and_wait(&mut self) -> Result<(), Box<dyn Error + Send>> {
+    /// 是否因为任务失败而被取消了(仅fail_fast模式)
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// 关闭线程池并等待所有任务执行完毕，返回所有失败任务的错误
+    pub fn close_and_wait(&mut self) -> Result<(), TaskErrors> {
         if self.is_terminated {
             return Ok(());
         }
@@ -145,9 +170,9 @@ impl BlockingThreadPool {
             }
         }
         
-        let err = self.error.lock().unwrap().take();
+        let errors = self.errors.lock().unwrap().take();
 
-        err.map_or_else(|| Ok(()), |e| Err(e))
+        if errors.is_empty() { Ok(()) } else { Err(errors) }
     }
 
     pub fn size(&self) -> u32 {
@@ -161,4 +186,99 @@ impl Drop for BlockingThreadPool {
             self.close_and_wait().unwrap();
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::sync::atomic::AtomicUsize;
+    use std::time::Duration;
+    use std::time::Instant;
+
+    use super::*;
+
+    fn failure(i: usize) -> Result<(), Box<dyn Error + Send>> {
+        Err(Box::new(io::Error::other(format!("task {} failed", i))))
+    }
+
+    /// 执行count个任务，failed返回true的任务会失败，返回实际执行了的任务数量和错误
+    fn run(size: usize, fail_fast: bool, count: usize, failed: fn(usize) -> bool) -> (usize, Result<(), TaskErrors>) {
+        let mut pool = BlockingThreadPool::new(size, fail_fast);
+        let executed = Arc::new(AtomicUsize::new(0));
+
+        for i in 0..count {
+            let executed = executed.clone();
+            pool.execute(move || {
+                executed.fetch_add(1, Ordering::SeqCst);
+                if failed(i) { failure(i) } else { Ok(()) }
+            });
+        }
+
+        let result = pool.close_and_wait();
+        (executed.load(Ordering::SeqCst), result)
+    }
+
+    #[test]
+    fn runs_tasks_concurrently() {
+        let mut pool = BlockingThreadPool::new(4, true);
+        let arrived = Arc::new(AtomicUsize::new(0));
+
+        // 每个任务都要等到4个任务同时在执行才会成功，串行执行时会超时失败
+        for i in 0..4 {
+            let arrived = arrived.clone();
+            pool.execute(move || {
+                arrived.fetch_add(1, Ordering::SeqCst);
+                let deadline = Instant::now() + Duration::from_secs(10);
+                while arrived.load(Ordering::SeqCst) < 4 {
+                    if Instant::now() > deadline {
+                        return failure(i);
+                    }
+                    thread::sleep(Duration::from_millis(1));
+                }
+                Ok(())
+            });
+        }
+
+        assert!(pool.close_and_wait().is_ok());
+    }
+
+    #[test]
+    fn collects_every_error() {
+        let (executed, result) = run(4, false, 8, |i| i % 2 == 0);
+        assert_eq!(executed, 8);
+
+        let mut errors = result.err().unwrap().iter().map(|e| e.to_string()).collect::<Vec<String>>();
+        errors.sort();
+        assert_eq!(errors, vec!["task 0 failed", "task 2 failed", "task 4 failed", "task 6 failed"]);
+    }
+
+    #[test]
+    fn fail_fast_drops_pending_tasks() {
+        // 只有一个线程时，第一个任务失败之后才会取出下一个任务
+        let (executed, result) = run(1, true, 8, |i| i == 0);
+        assert_eq!(executed, 1);
+        assert_eq!(result.err().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn keeps_going_without_fail_fast() {
+        let (executed, result) = run(1, false, 8, |i| i == 0);
+        assert_eq!(executed, 8);
+        assert_eq!(result.err().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn reports_cancellation() {
+        let mut pool = BlockingThreadPool::new(1, true);
+        pool.execute(|| failure(0));
+        // 同步通道保证上一个任务结束之后这个任务才会被取出
+        pool.execute(|| Ok(()));
+        assert!(pool.is_cancelled());
+        assert!(pool.close_and_wait().is_err());
+
+        let mut pool = BlockingThreadPool::new(1, false);
+        pool.execute(|| failure(0));
+        pool.execute(|| Ok(()));
+        assert!(!pool.is_cancelled());
+        assert!(pool.close_and_wait().is_err());
+    }
+}
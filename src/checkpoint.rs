@@ -0,0 +1,166 @@
+use std::sync::Condvar;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+/// 运行过程中定期保存状态文件(检查点)的配置，operations和interval都未配置时不保存检查点
+#[derive(Clone)]
+pub struct CheckpointConfig {
+    /// 每完成多少个操作保存一次，0表示不按操作数量保存
+    pub operations: u64,
+    /// 每隔多长时间保存一次(期间有操作完成时)
+    pub interval: Option<Duration>,
+    /// 保存检查点时是否同时更新远端的状态文件(仅开启use-remote-state时有效)
+    pub upload_state: bool,
+}
+
+impl CheckpointConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.operations > 0 || self.interval.is_some()
+    }
+}
+
+struct Progress {
+    /// 上次保存检查点之后完成的操作数量
+    pending: u64,
+    finished: bool,
+}
+
+/// 统计已完成的操作，并通知保存检查点的线程
+pub struct Checkpoint {
+    config: CheckpointConfig,
+    progress: Mutex<Progress>,
+    condvar: Condvar,
+}
+
+impl Checkpoint {
+    pub fn new(config: &CheckpointConfig) -> Checkpoint {
+        Checkpoint {
+            config: config.clone(),
+            progress: Mutex::new(Progress { pending: 0, finished: false }),
+            condvar: Condvar::new(),
+        }
+    }
+
+    pub fn config(&self) -> &CheckpointConfig {
+        &self.config
+    }
+
+    /// 记录一个已经完成(并且已经更新到状态里)的操作
+    pub fn record(&self) {
+        if !self.config.is_enabled() {
+            return;
+        }
+
+        self.progress.lock().unwrap().pending += 1;
+        self.condvar.notify_all();
+    }
+
+    /// 所有操作都已经执行完毕，wait会立即返回false
+    pub fn finish(&self) {
+        self.progress.lock().unwrap().finished = true;
+        self.condvar.notify_all();
+    }
+
+    /// 阻塞直到需要保存下一个检查点时返回true，调用finish之后返回false
+    pub fn wait(&self) -> bool {
+        let deadline = self.config.interval.map(|interval| Instant::now() + interval);
+        let mut progress = self.progress.lock().unwrap();
+
+        loop {
+            if progress.finished {
+                return false;
+            }
+
+            let operations_due = self.config.operations > 0 && progress.pending >= self.config.operations;
+            let interval_due = progress.pending > 0 && deadline.is_some_and(|d| Instant::now() >= d);
+
+            if operations_due || interval_due {
+                progress.pending = 0;
+                return true;
+            }
+
+            progress = match deadline {
+                Some(deadline) => self.condvar.wait_timeout(progress, deadline.saturating_duration_since(Instant::now())).unwrap().0,
+                None => self.condvar.wait(progress).unwrap(),
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    fn checkpoint(operations: u64, interval: Option<Duration>) -> Checkpoint {
+        Checkpoint::new(&CheckpointConfig { operations, interval, upload_state: false })
+    }
+
+    fn record(checkpoint: &Checkpoint, count: usize) {
+        for _ in 0..count {
+            checkpoint.record();
+        }
+    }
+
+    #[test]
+    fn saves_after_enough_operations() {
+        let checkpoint = checkpoint(3, None);
+        record(&checkpoint, 4);
+        assert!(checkpoint.wait());
+
+        // 保存之后重新计数，剩余的1个操作不足以触发下一次保存
+        record(&checkpoint, 1);
+        checkpoint.finish();
+        assert!(!checkpoint.wait());
+    }
+
+    #[test]
+    fn waits_for_operations_in_another_thread() {
+        let checkpoint = checkpoint(3, None);
+
+        thread::scope(|scope| {
+            let waiter = scope.spawn(|| checkpoint.wait());
+            record(&checkpoint, 3);
+            assert!(waiter.join().unwrap());
+        });
+    }
+
+    #[test]
+    fn saves_after_interval_only_when_something_finished() {
+        // 间隔为0时，只要有完成的操作就立即保存
+        let checkpoint = checkpoint(0, Some(Duration::ZERO));
+        record(&checkpoint, 1);
+        assert!(checkpoint.wait());
+
+        // 没有完成任何操作时不保存，直到finish
+        thread::scope(|scope| {
+            let waiter = scope.spawn(|| checkpoint.wait());
+            checkpoint.finish();
+            assert!(!waiter.join().unwrap());
+        });
+    }
+
+    #[test]
+    fn finish_ends_waiting() {
+        let checkpoint = checkpoint(100, Some(Duration::from_secs(3600)));
+        record(&checkpoint, 1);
+
+        thread::scope(|scope| {
+            let waiter = scope.spawn(|| checkpoint.wait());
+            checkpoint.finish();
+            assert!(!waiter.join().unwrap());
+        });
+    }
+
+    #[test]
+    fn disabled_checkpoint_ignores_operations() {
+        let checkpoint = checkpoint(0, None);
+        assert!(!checkpoint.config().is_enabled());
+
+        record(&checkpoint, 10);
+        checkpoint.finish();
+        assert!(!checkpoint.wait());
+    }
+}
@@ -0,0 +1,339 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Read;
+use std::io::Result;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process;
+use std::sync::Condvar;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use crate::file::File;
+use crate::hash_algorithm::HashAlgorithm;
+
+/// 分块上传的配置，分块的大小在avg_chunk_size的1/4到4倍之间
+#[derive(Clone)]
+pub struct ChunkingConfig {
+    /// 大于等于此大小的文件才会被分块上传
+    pub min_file_size: u64,
+    /// 分块的平均大小
+    pub avg_chunk_size: u64,
+}
+
+/// 文件中的一个分块，分块按顺序排列，偏移量为前面所有分块的长度之和
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Chunk {
+    pub hash: String,
+    pub length: u64,
+}
+
+/// Gear哈希使用的随机数表(使用splitmix64生成，保证每次运行的分块结果都一样)
+const GEAR: [u64; 256] = gear_table();
+
+const fn gear_table() -> [u64; 256] {
+    let mut table = [0u64; 256];
+    let mut seed: u64 = 0x6a09e667f3bcc908;
+    let mut i = 0;
+
+    while i < 256 {
+        seed = seed.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = seed;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+
+    table
+}
+
+impl ChunkingConfig {
+    pub fn min_chunk_size(&self) -> u64 {
+        (self.avg_chunk_size / 4).max(1)
+    }
+
+    pub fn max_chunk_size(&self) -> u64 {
+        self.avg_chunk_size.saturating_mul(4)
+    }
+
+    /// 使用内容定义分块(Gear滚动哈希)对文件进行分块，并计算每个分块的hash<br/>
+    /// 文件中间插入或者删除内容时，只有附近的分块会发生变化
+    pub fn split(&self, file: &File, algorithm: HashAlgorithm) -> Result<Vec<Chunk>> {
+        let min_size = self.min_chunk_size();
+        let max_size = self.max_chunk_size();
+        // 滚动哈希的高位为0时切分，平均每avg_chunk_size个字节切分一次
+        let bits = 63 - self.avg_chunk_size.max(2).leading_zeros();
+        let shift = 64 - bits;
+
+        let f = fs::File::open(file.path())?;
+        let mut reader = BufReader::with_capacity(1024 * 1024, f);
+        let mut chunks = Vec::new();
+        let mut hasher = algorithm.hasher();
+        let mut length: u64 = 0;
+        let mut rolling: u64 = 0;
+
+        loop {
+            let buf = reader.fill_buf()?;
+            let reads = buf.len();
+            if reads == 0 {
+                break;
+            }
+
+            let mut start = 0;
+            for (i, b) in buf.iter().enumerate() {
+                rolling = (rolling << 1).wrapping_add(GEAR[*b as usize]);
+                length += 1;
+
+                if (length >= min_size && rolling >> shift == 0) || length >= max_size {
+                    hasher.update(&buf[start..=i]);
+                    let finished = std::mem::replace(&mut hasher, algorithm.hasher());
+                    chunks.push(Chunk { hash: finished.finalize(), length });
+
+                    start = i + 1;
+                    length = 0;
+                    rolling = 0;
+                }
+            }
+
+            hasher.update(&buf[start..reads]);
+            reader.consume(reads);
+        }
+
+        if length > 0 {
+            chunks.push(Chunk { hash: hasher.finalize(), length });
+        }
+
+        Ok(chunks)
+    }
+}
+
+/// 本次运行存放分块和清单的临时目录: <系统临时目录>/incremental-upload-chunks/<进程id>-<时间戳><br/>
+/// 每次运行使用单独的目录，同时运行的多个进程不会互相覆盖临时文件，运行结束后整个目录会被删除
+pub fn run_temp_dir() -> PathBuf {
+    let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default();
+    std::env::temp_dir()
+        .join("incremental-upload-chunks")
+        .join(format!("{}-{}", process::id(), now.as_nanos()))
+}
+
+/// 判断路径是否为run_temp_dir()格式的临时目录，计划文件中的temp-dir只接受这样的目录，避免执行计划后删除其它目录
+pub fn is_run_temp_dir(dir: &Path) -> bool {
+    let root = std::env::temp_dir().join("incremental-upload-chunks");
+    let name = match dir.strip_prefix(&root).ok().map(|name| name.components().collect::<Vec<_>>()) {
+        Some(components) if components.len() == 1 => components[0].as_os_str().to_string_lossy().to_string(),
+        _ => return false,
+    };
+
+    matches!(name.split_once('-'), Some((pid, nanos)) if pid.parse::<u32>().is_ok() && nanos.parse::<u128>().is_ok())
+}
+
+/// 分块的临时文件: <dir>/chunks/<分块hash>，KnownChunks保证同一个分块同时只会被一个线程写入和上传
+pub fn chunk_file(dir: &Path, hash: &str) -> File {
+    File::from(dir.join("chunks").join(hash))
+}
+
+/// 清单的临时文件: <dir>/manifests/<文件的相对路径>.manifest，每个文件使用单独的清单文件(内容相同的文件也不会冲突)
+pub fn manifest_file(dir: &Path, path: &str) -> File {
+    File::from(dir.join("manifests").join(format!("{}.manifest", path)))
+}
+
+/// 把文件中的一个分块写入临时文件，返回临时文件的路径
+pub fn write_chunk(dir: &Path, file: &File, offset: u64, chunk: &Chunk) -> Result<File> {
+    let dest = chunk_file(dir, &chunk.hash);
+    dest.parent()?.unwrap().mkdirs()?;
+
+    let mut reader = fs::File::open(file.path())?;
+    reader.seek(SeekFrom::Start(offset))?;
+    let mut writer = fs::File::create(dest.path())?;
+    std::io::copy(&mut reader.take(chunk.length), &mut writer)?;
+
+    Ok(dest)
+}
+
+/// 写入清单文件，每行为一个分块: hash 长度
+pub fn write_manifest(dest: &File, chunks: &[Chunk]) -> Result<()> {
+    dest.parent()?.unwrap().mkdirs()?;
+
+    let contents = chunks.iter()
+        .map(|c| format!("{} {}\n", c.hash, c.length))
+        .collect::<String>();
+
+    fs::write(dest.get_raw(), contents)
+}
+
+struct ChunkSet {
+    /// 远端已有(或者本次已经上传成功)的分块
+    known: HashSet<String>,
+    /// 正在被某个线程上传的分块
+    uploading: HashSet<String>,
+}
+
+/// 远端已有的分块，多个线程上传包含相同分块的文件时，每个分块只会由一个线程上传<br/>
+/// 其它线程会等待上传结束，上传失败时由下一个线程重新上传，这样清单总是在分块上传成功之后才会上传
+pub struct KnownChunks {
+    chunks: Mutex<ChunkSet>,
+    condvar: Condvar,
+}
+
+impl KnownChunks {
+    pub fn new(known: HashSet<String>) -> KnownChunks {
+        KnownChunks {
+            chunks: Mutex::new(ChunkSet { known, uploading: HashSet::new() }),
+            condvar: Condvar::new(),
+        }
+    }
+
+    /// 分块已经在远端时返回false；否则标记为正在上传并返回true，调用者上传之后需要调用finish
+    pub fn begin(&self, hash: &str) -> bool {
+        let mut chunks = self.chunks.lock().unwrap();
+
+        loop {
+            if chunks.known.contains(hash) {
+                return false;
+            }
+
+            if chunks.uploading.insert(hash.to_owned()) {
+                return true;
+            }
+
+            chunks = self.condvar.wait(chunks).unwrap();
+        }
+    }
+
+    /// 结束上传，uploaded为false(上传失败)时其它线程会重新上传这个分块
+    pub fn finish(&self, hash: &str, uploaded: bool) {
+        let mut chunks = self.chunks.lock().unwrap();
+        chunks.uploading.remove(hash);
+        if uploaded {
+            chunks.known.insert(hash.to_owned());
+        }
+
+        self.condvar.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+    use std::thread;
+
+    use super::*;
+    use crate::test_utils::TempDir;
+
+    const CONFIG: ChunkingConfig = ChunkingConfig { min_file_size: 0, avg_chunk_size: 1024 };
+
+    /// 使用固定种子生成的伪随机数据
+    fn random_data(length: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed;
+        (0..length).map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state >> 24) as u8
+        }).collect()
+    }
+
+    fn split(name: &str, data: &[u8], config: &ChunkingConfig) -> Vec<Chunk> {
+        let dir = TempDir::new(&format!("chunker-{}", name));
+        let file = dir.file("data");
+        fs::write(file.get_raw(), data).unwrap();
+        config.split(&file, HashAlgorithm::Sha1).unwrap()
+    }
+
+    #[test]
+    fn splits_deterministically() {
+        let data = random_data(64 * 1024, 1);
+        let chunks = split("deterministic", &data, &CONFIG);
+
+        assert_eq!(chunks, split("deterministic", &data, &CONFIG));
+        assert!(chunks.len() > 1);
+        assert_eq!(chunks.iter().map(|c| c.length).sum::<u64>(), data.len() as u64);
+    }
+
+    #[test]
+    fn respects_chunk_size_limits() {
+        let data = random_data(256 * 1024, 2);
+        let chunks = split("limits", &data, &CONFIG);
+
+        // 最后一个分块可以小于最小值
+        for chunk in &chunks[..chunks.len() - 1] {
+            assert!(chunk.length >= CONFIG.min_chunk_size(), "{} < {}", chunk.length, CONFIG.min_chunk_size());
+        }
+        assert!(chunks.iter().all(|c| c.length <= CONFIG.max_chunk_size()));
+
+        // 内容完全相同(没有切分点)时按照最大值切分
+        let zeros = split("zeros", &vec![0u8; 10 * 1024], &CONFIG);
+        assert!(zeros[..zeros.len() - 1].iter().all(|c| c.length == CONFIG.max_chunk_size()));
+    }
+
+    #[test]
+    fn inserted_byte_only_changes_nearby_chunks() {
+        let data = random_data(64 * 1024, 3);
+        let mut modified = data.clone();
+        modified.insert(32 * 1024, 0x5a);
+
+        let before = split("before", &data, &CONFIG);
+        let after = split("after", &modified, &CONFIG);
+
+        // 插入位置之前和之后的分块都不受影响，只有中间的分块发生变化(并且整体向后偏移了一个字节)
+        let before_hashes = before.iter().map(|c| &c.hash).collect::<HashSet<&String>>();
+        let changed = after.iter().filter(|c| !before_hashes.contains(&c.hash)).count();
+        assert!(changed >= 1 && changed <= 2, "{} chunks changed", changed);
+        assert_eq!(before.first(), after.first());
+        assert_eq!(before.last(), after.last());
+    }
+
+    #[test]
+    fn uses_separate_temp_files_per_run_and_file() {
+        let dir = run_temp_dir();
+        assert_ne!(dir, run_temp_dir());
+        assert_ne!(manifest_file(&dir, "a/b.txt").path(), manifest_file(&dir, "c.txt").path());
+        assert!(chunk_file(&dir, "abc").get_raw().starts_with(&dir));
+    }
+
+    #[test]
+    fn accepts_only_run_temp_dirs() {
+        let root = std::env::temp_dir().join("incremental-upload-chunks");
+        assert!(is_run_temp_dir(&run_temp_dir()));
+        assert!(!is_run_temp_dir(&root));
+        assert!(!is_run_temp_dir(&root.join("..")));
+        assert!(!is_run_temp_dir(&root.join("1-2").join("chunks")));
+        assert!(!is_run_temp_dir(&root.join("home")));
+        assert!(!is_run_temp_dir(&std::env::temp_dir().join("1-2")));
+        assert!(!is_run_temp_dir(Path::new("/root")));
+    }
+
+    #[test]
+    fn uploads_each_chunk_once() {
+        let known = Arc::new(KnownChunks::new(["a".to_owned()].into_iter().collect()));
+        let uploads = Arc::new(AtomicUsize::new(0));
+
+        assert!(!known.begin("a"));
+
+        thread::scope(|scope| {
+            for _ in 0..8 {
+                let (known, uploads) = (known.clone(), uploads.clone());
+                scope.spawn(move || {
+                    if known.begin("b") {
+                        uploads.fetch_add(1, Ordering::SeqCst);
+                        thread::sleep(std::time::Duration::from_millis(10));
+                        known.finish("b", true);
+                    }
+                });
+            }
+        });
+        assert_eq!(uploads.load(Ordering::SeqCst), 1);
+
+        // 上传失败时由下一个线程重新上传
+        assert!(known.begin("c"));
+        known.finish("c", false);
+        assert!(known.begin("c"));
+    }
+}
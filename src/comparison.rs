@@ -0,0 +1,136 @@
+use std::time::Duration;
+
+use crate::file::File;
+use crate::hash_cache::HashCache;
+use crate::simple_file::FileData;
+
+/// 判断文件内容是否发生变化的方式
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Comparison {
+    /// 修改时间一致时视为没有变化，否则对比大小和hash
+    Mtime,
+    /// 大小不一致时直接视为有变化，大小和修改时间都一致时视为没有变化，否则对比hash
+    SizeMtime,
+    /// 大小不一致时直接视为有变化，否则对比hash
+    Hash,
+    /// 和hash相同，但是不使用hash缓存文件，所有的hash都会重新计算
+    Paranoid,
+}
+
+impl Comparison {
+    pub fn from_name(name: &str) -> Option<Comparison> {
+        match &name.to_lowercase()[..] {
+            "mtime" => Some(Comparison::Mtime),
+            "size-mtime" => Some(Comparison::SizeMtime),
+            "hash" => Some(Comparison::Hash),
+            "paranoid" => Some(Comparison::Paranoid),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Comparison::Mtime => "mtime",
+            Comparison::SizeMtime => "size-mtime",
+            Comparison::Hash => "hash",
+            Comparison::Paranoid => "paranoid",
+        }
+    }
+
+    /// 判断本地文件与状态中记录的文件是否一致，只有在无法通过大小和修改时间判断时才会计算hash
+    pub fn is_unchanged(&self, remote: &FileData, local: &File, path: &str, hash_cache: &HashCache, mtime_tolerance: Duration, debug_mode: bool) -> bool {
+        if *self == Comparison::Mtime && remote.mtime_matches(local, mtime_tolerance) {
+            return true;
+        }
+
+        // 大小不一致时内容一定发生了变化，不需要计算hash
+        if !local.length().is_ok_and(|length| length == remote.length) {
+            return false;
+        }
+
+        if *self == Comparison::SizeMtime && remote.mtime_matches(local, mtime_tolerance) {
+            return true;
+        }
+
+        remote.hash == hash_cache.get_hash(path, debug_mode)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash_algorithm::HashAlgorithm;
+    use crate::test_utils::TempDir;
+    use crate::test_utils::set_mtime;
+
+    const ALL: [Comparison; 4] = [Comparison::Mtime, Comparison::SizeMtime, Comparison::Hash, Comparison::Paranoid];
+    const MODIFIED: Duration = Duration::from_secs(1_700_000_000);
+
+    struct Fixture {
+        temp: TempDir,
+        file: File,
+        hash: String,
+    }
+
+    impl Fixture {
+        /// 创建内容为abc，修改时间为MODIFIED的文件
+        fn new(name: &str) -> Fixture {
+            let temp = TempDir::new(&format!("comparison-{}", name));
+            let file = temp.write("a.txt", "abc");
+            set_mtime(&file, MODIFIED);
+
+            let hash = file.hash(HashAlgorithm::Sha1).unwrap();
+            Fixture { temp, file, hash }
+        }
+
+        fn recorded(&self, length: u64, hash: &str, modified: Duration) -> FileData {
+            FileData { modified_ns: Some(modified.as_nanos() as u64), ..FileData::new(length, hash.to_owned(), modified.as_secs()) }
+        }
+
+        /// 每种对比方式是否认为文件没有变化
+        fn unchanged(&self, remote: &FileData) -> Vec<bool> {
+            ALL.iter()
+                .map(|c| c.is_unchanged(remote, &self.file, "a.txt", &HashCache::new(&self.temp.dir, HashAlgorithm::Sha1), Duration::ZERO, false))
+                .collect()
+        }
+    }
+
+    #[test]
+    fn parses_names() {
+        for comparison in ALL {
+            assert_eq!(Comparison::from_name(comparison.name()), Some(comparison));
+        }
+        assert_eq!(Comparison::from_name("SIZE-MTIME"), Some(Comparison::SizeMtime));
+        assert_eq!(Comparison::from_name("size"), None);
+    }
+
+    #[test]
+    fn unchanged_file_is_unchanged_for_every_strategy() {
+        let fixture = Fixture::new("unchanged");
+        let remote = fixture.recorded(3, &fixture.hash, MODIFIED);
+        assert_eq!(fixture.unchanged(&remote), [true, true, true, true]);
+    }
+
+    #[test]
+    fn mtime_strategies_trust_matching_mtime() {
+        // 修改时间和大小都没变，但内容变了(比如被工具保留了修改时间)
+        let fixture = Fixture::new("same-mtime");
+        let remote = fixture.recorded(3, "stale", MODIFIED);
+        assert_eq!(fixture.unchanged(&remote), [true, true, false, false]);
+    }
+
+    #[test]
+    fn size_change_is_a_change_unless_only_mtime_is_compared() {
+        let fixture = Fixture::new("size");
+        let remote = fixture.recorded(4, &fixture.hash, MODIFIED);
+        assert_eq!(fixture.unchanged(&remote), [true, false, false, false]);
+    }
+
+    #[test]
+    fn falls_back_to_hash_when_mtime_differs() {
+        let fixture = Fixture::new("touched");
+        let touched = MODIFIED - Duration::from_secs(10);
+        assert_eq!(fixture.unchanged(&fixture.recorded(3, &fixture.hash, touched)), [true, true, true, true]);
+        assert_eq!(fixture.unchanged(&fixture.recorded(3, "stale", touched)), [false, false, false, false]);
+    }
+}
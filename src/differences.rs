@@ -3,6 +3,12 @@ pub struct Differences {
     pub old_folders: Vec<String>,
     pub new_files: Vec<String>,
     pub new_folders: Vec<String>,
+    /// 内容没有变化，只是路径发生变化的文件(原路径, 新路径)
+    pub moved_files: Vec<(String, String)>,
+    /// 新增或者指向的路径发生变化的符号链接(仅preserve模式)
+    pub new_symlinks: Vec<String>,
+    /// 内容没有变化，只有权限发生变化的文件
+    pub chmod_files: Vec<String>,
 }
 
 impl Differences {
@@ -12,6 +18,9 @@ impl Differences {
             old_folders: Vec::new(), 
             new_files: Vec::new(), 
             new_folders: Vec::new(),
+            moved_files: Vec::new(),
+            new_symlinks: Vec::new(),
+            chmod_files: Vec::new(),
         }
     }
 
@@ -19,6 +28,9 @@ impl Differences {
         self.old_files.len() +
         self.old_folders.len() +
         self.new_files.len() +
-        self.new_folders.len() > 0
+        self.new_folders.len() +
+        self.moved_files.len() +
+        self.new_symlinks.len() +
+        self.chmod_files.len() > 0
     }
 }
\ No newline at end of file
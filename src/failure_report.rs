@@ -0,0 +1,99 @@
+use std::sync::Mutex;
+
+use crate::progress;
+
+/// 创建目录的操作类型，记录和查找创建失败的目录时都使用这个常量(不要直接使用输出的文本)
+pub const MAKE_DIR: &str = "新目录";
+
+/// 一次执行失败的操作
+pub struct Failure {
+    /// 操作类型，比如: 上传文件
+    pub operation: String,
+    /// 操作的文件路径
+    pub path: String,
+    /// 失败原因
+    pub message: String,
+}
+
+/// 收集keep-going模式下执行失败的操作，在结束时统一报告
+pub struct FailureReport {
+    failures: Mutex<Vec<Failure>>,
+}
+
+impl FailureReport {
+    pub fn new() -> FailureReport {
+        FailureReport { failures: Mutex::new(Vec::new()) }
+    }
+
+    /// 记录一个失败的操作
+    pub fn record(&self, operation: &str, path: &str, message: &str) {
+        self.failures.lock().unwrap().push(Failure {
+            operation: operation.to_owned(),
+            path: path.to_owned(),
+            message: message.to_owned(),
+        });
+    }
+
+    /// 指定操作类型中所有失败的路径
+    pub fn paths(&self, operation: &str) -> Vec<String> {
+        self.failures.lock().unwrap().iter()
+            .filter(|f| f.operation == operation)
+            .map(|f| f.path.to_owned())
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.failures.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// 输出所有失败的操作
+    pub fn print_summary(&self) {
+        let failures = self.failures.lock().unwrap();
+
+        progress!("\n以下{}个操作执行失败，将在下次运行时重试:", failures.len());
+        for f in failures.iter() {
+            progress!("  {}: {}", f.operation, f.path);
+            progress!("    {}", f.message);
+        }
+    }
+}
+
+impl Default for FailureReport {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_failures() {
+        let report = FailureReport::new();
+        assert!(report.is_empty());
+
+        report.record(MAKE_DIR, "a", "error 1");
+        report.record("新文件", "a/b.txt", "error 2");
+        report.record(MAKE_DIR, "c/d", "error 3");
+
+        assert_eq!(report.len(), 3);
+        assert!(!report.is_empty());
+    }
+
+    #[test]
+    fn filters_paths_by_operation() {
+        let report = FailureReport::new();
+        report.record(MAKE_DIR, "a", "error 1");
+        report.record("新文件", "a/b.txt", "error 2");
+        report.record(MAKE_DIR, "c/d", "error 3");
+
+        assert_eq!(report.paths(MAKE_DIR), vec!["a".to_owned(), "c/d".to_owned()]);
+        assert_eq!(report.paths("新文件"), vec!["a/b.txt".to_owned()]);
+        assert!(report.paths("删除文件").is_empty());
+    }
+}
@@ -4,14 +4,15 @@ use std::io::BufRead;
 use std::io::BufReader;
 use std::io::Error;
 use std::io::Result;
+use std::io::Write;
 use std::path::PathBuf;
 use std::io::ErrorKind;
 use std::time::SystemTime;
 
-use hex::ToHex;
 use path_absolutize::Absolutize;
 use relative_path::RelativePath;
-use sha1::{Sha1, Digest};
+
+use crate::hash_algorithm::HashAlgorithm;
 
 pub struct DirectoryIterator<'a>(&'a File, ReadDir);
 
@@ -166,14 +167,15 @@ impl File {
     }
 
     pub fn rm(&self) -> Result<()> {
-        if !self.exists() {
+        if !self.exists() && !self.is_symlink() {
             return Err(Error::new(
                 ErrorKind::NotFound, 
                 String::from("source path: ") + &self.path()
             ));
         }
 
-        if self.is_dir() {
+        // 只删除符号链接本身，不删除链接指向的内容
+        if self.is_dir() && !self.is_symlink() {
             fs::remove_dir_all(self.path())?;
         } else {
             fs::remove_file(self.path())?;
@@ -190,6 +192,27 @@ impl File {
             .as_secs())
     }
 
+    /// 纳秒精度的修改时间，实际精度取决于文件系统
+    pub fn modified_ns(&self) -> Result<u64> {
+        Ok(self.raw.metadata()?
+            .modified()?
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_nanos() as u64)
+    }
+
+    /// 文件的权限位(比如0o755)，不支持POSIX权限的平台上返回None
+    pub fn mode(&self) -> Result<Option<u32>> {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            Ok(Some(self.raw.metadata()?.permissions().mode() & 0o7777))
+        }
+
+        #[cfg(not(unix))]
+        Ok(None)
+    }
+
     pub fn created(&self) -> Result<u64> {
         Ok(self.raw.metadata()?
             .created()?
@@ -209,6 +232,33 @@ impl File {
         fs::write(self.path(), contents)
     }
 
+    /// 先写入同一目录下的临时文件并同步到磁盘，再重命名为目标文件(会覆盖已有的文件)<br/>
+    /// 写入过程中出错或者程序崩溃时，原来的文件不会被破坏
+    pub fn write_atomically(&self, contents: &[u8]) -> Result<()> {
+        let mut temp = self.raw.clone().into_os_string();
+        temp.push(".tmp");
+        let temp = PathBuf::from(temp);
+
+        let result = fs::File::create(&temp).and_then(|mut f| {
+            f.write_all(contents)?;
+            f.sync_all()
+        });
+        if let Err(e) = result {
+            let _ = fs::remove_file(&temp);
+            return Err(e);
+        }
+
+        fs::rename(&temp, &self.raw)?;
+
+        // 同步所在的目录，保证重命名本身也被写入磁盘
+        #[cfg(unix)]
+        if let Some(parent) = self.raw.parent() {
+            fs::File::open(parent)?.sync_all()?;
+        }
+
+        Ok(())
+    }
+
     pub fn read(&self) -> Result<String> {
         if !self.exists() {
             return Err(Error::new(
@@ -266,7 +316,11 @@ impl File {
     }
 
     pub fn sha1(&self) -> Result<String> {
-        let mut hasher = Sha1::new();
+        self.hash(HashAlgorithm::Sha1)
+    }
+
+    pub fn hash(&self, algorithm: HashAlgorithm) -> Result<String> {
+        let mut hasher = algorithm.hasher();
 
         let file_len = self.length()?;
         let kb = 1024;
@@ -309,10 +363,7 @@ impl File {
             }
         }
 
-        let bytes = &hasher.finalize()[..];
-        let sha1 = bytes.encode_hex::<String>();
-        
-        Ok(sha1)
+        Ok(hasher.finalize())
     }
     
 }
@@ -1,3 +1,4 @@
+use crate::comparison::Comparison;
 use crate::differences::Differences;
 use crate::file::File;
 use crate::file_state::State;
@@ -5,32 +6,49 @@ use crate::hash_cache::HashCache;
 use crate::rule_filter::RuleFilter;
 use crate::simple_file::FileData;
 use crate::simple_file::SimpleFile;
+use crate::walk_filter::WalkFilter;
 
+use std::collections::HashMap;
+use std::collections::HashSet;
 use std::io::Error;
 use std::io::Result;
 
+/// 判断文件是否没有变化的函数: (状态中记录的文件, 本地文件, 相对路径, 对比方式, hash缓存, debug模式)
+pub type CompareFunc = dyn Fn(&FileData, &File, &str, Comparison, &HashCache, bool) -> bool;
+
 pub struct FileComparer<'a> {
     pub base_path: File,
-    pub compare_func: Box<dyn Fn(&FileData, &File, &str, bool, &HashCache, bool) -> bool>,
+    pub compare_func: Box<CompareFunc>,
     pub hash_cache: &'a HashCache,
     pub debug_mode: bool,
-    pub fast_comparison: bool,
+    pub comparison: Comparison,
     pub filters: &'a RuleFilter,
+    pub walk_filter: &'a WalkFilter,
     pub differences: Differences,
+    /// 状态文件的hash算法与hash_cache不一致时，用来对比文件的hash缓存
+    fallback_cache: Option<HashCache>,
+    /// 需要使用新算法重新计算hash的文件(内容未变化的文件)，仅在hash算法不一致时存在
+    pub rehash_files: Option<Vec<String>>,
+    /// 旧版本的状态文件中没有记录权限的文件以及它们当前的权限，只需要记录到状态文件里，不需要执行chmod-file
+    pub unrecorded_modes: Vec<(String, u32)>,
 }
 
 impl FileComparer<'_> {
-    pub fn new<'a, F>(base_path: &File, compare_func: F, hash_cache: &'a HashCache, fast_comparison: bool, filters: &'a RuleFilter, debug_mode: bool) -> FileComparer<'a>
-        where F : Fn(&FileData, &File, &str, bool, &HashCache, bool) -> bool + 'static
+    pub fn new<'a, F>(base_path: &File, compare_func: F, hash_cache: &'a HashCache, comparison: Comparison, filters: &'a RuleFilter, walk_filter: &'a WalkFilter, debug_mode: bool) -> FileComparer<'a>
+        where F : Fn(&FileData, &File, &str, Comparison, &HashCache, bool) -> bool + 'static
     {
         FileComparer { 
             base_path: base_path.clone(), 
             compare_func: Box::new(compare_func),
             hash_cache,
             debug_mode,
-            fast_comparison,
+            comparison,
             filters,
+            walk_filter,
             differences: Differences::new(),
+            fallback_cache: None,
+            rehash_files: None,
+            unrecorded_modes: Vec::new(),
         }
     }
 
@@ -43,9 +61,28 @@ impl FileComparer<'_> {
         for t in contrast.files()? {
             let t = t?;
 
+            // 被忽略的文件和目录直接跳过，不进行任何对比
+            if self.walk_filter.is_ignored(&t) {
+                continue;
+            }
+
+            // preserve模式下不跟随符号链接，只对比链接指向的路径
+            if self.walk_filter.preserves(&t) {
+                let link = SimpleFile::from_real_symlink(&t)?;
+                match directory.get_file(t.name()) {
+                    Some(corresponding) if corresponding.as_file().is_some_and(|c| c.link_target == link.as_file().unwrap().link_target) => (),
+                    Some(corresponding) => {
+                        self.add_old(corresponding, &contrast.relativized_by(&self.base_path))?;
+                        self.add_new(&link, &t)?;
+                    },
+                    None => self.add_new(&link, &t)?,
+                }
+                continue;
+            }
+
             if !directory.contains_file(t.name()) { // 文件不存在
                 let sf: Option<SimpleFile> = if t.is_dir() {
-                    Some(SimpleFile::from_real_directory(&t, Some((self.hash_cache, &self.base_path, self.debug_mode)))?)
+                    Some(SimpleFile::from_real_directory(&t, Some((self.hash_cache, &self.base_path, self.debug_mode)), Some(self.walk_filter))?)
                 } else if t.is_file() {
                     Some(SimpleFile::from_real_file(&t, Some((self.hash_cache, &self.base_path, self.debug_mode)))?)
                 } else {
@@ -63,21 +100,42 @@ impl FileComparer<'_> {
                     if corresponding.is_file() {
                         // 先删除旧的再获取新的
                         self.add_old(corresponding, &contrast.relativized_by(&self.base_path))?;
-                        self.add_new(corresponding, &t)?;
+                        let sf = SimpleFile::from_real_directory(&t, Some((self.hash_cache, &self.base_path, self.debug_mode)), Some(self.walk_filter))?;
+                        self.add_new(&sf, &t)?;
                     } else if corresponding.is_dir() {
                         self.find_new_files(&corresponding, &t)?;
                     }
                 } else {
                     if corresponding.is_file() {
-                        if !(self.compare_func)(&corresponding.as_file().unwrap(), &t, &t.relativized_by(&self.base_path), self.fast_comparison, self.hash_cache, self.debug_mode) {
+                        let path = t.relativized_by(&self.base_path);
+                        let hash_cache = self.fallback_cache.as_ref().unwrap_or(self.hash_cache);
+                        let unchanged = (self.compare_func)(corresponding.as_file().unwrap(), &t, &path, self.comparison, hash_cache, self.debug_mode);
+
+                        if !unchanged {
                             // 先删除旧的再获取新的
                             self.add_old(corresponding, &contrast.relativized_by(&self.base_path))?;
-                            self.add_new(corresponding, &t)?;
+                            let sf = SimpleFile::from_real_file(&t, Some((self.hash_cache, &self.base_path, self.debug_mode)))?;
+                            self.add_new(&sf, &t)?;
+                        } else {
+                            // 内容没有变化时单独检查权限，只有权限变化的文件不需要重新上传
+                            // 状态文件中没有记录权限时无法判断是否发生了变化，只记录当前的权限
+                            if let Some(mode) = t.mode()? {
+                                match corresponding.as_file().unwrap().mode {
+                                    None => self.unrecorded_modes.push((path.to_owned(), mode)),
+                                    Some(recorded) if recorded != mode && self.filter(&path, false) => self.differences.chmod_files.push(path.to_owned()),
+                                    Some(_) => (),
+                                }
+                            }
+
+                            if let Some(rehash_files) = &mut self.rehash_files {
+                                rehash_files.push(path);
+                            }
                         }
                     } else {
                         // 先删除旧的再获取新的
                         self.add_old(&corresponding, &contrast.relativized_by(&self.base_path))?;
-                        self.add_new(&corresponding, &t)?;
+                        let sf = SimpleFile::from_real_file(&t, Some((self.hash_cache, &self.base_path, self.debug_mode)))?;
+                        self.add_new(&sf, &t)?;
                     }
                 }
             }
@@ -94,9 +152,10 @@ impl FileComparer<'_> {
         for f in &directory.as_dir().unwrap().files {
             let corresponding = contrast.append(&f.name)?;
 
-            if corresponding.exists() {
+            // 指向的路径不存在的符号链接也需要保留
+            if corresponding.exists() || corresponding.is_symlink() {
                 // 如果两边都是目录，递归并进一步判断
-                if f.is_dir() && corresponding.is_dir() {
+                if f.is_dir() && corresponding.is_dir() && !self.walk_filter.preserves(&corresponding) {
                     self.find_old_files(&f, &corresponding)?;
                 }
                 // 其它情况均由findMissingFiles进行处理了，这里不需要重复计算
@@ -112,7 +171,7 @@ impl FileComparer<'_> {
     /// missing: 缺失的文件对象(文件/目录)<br/>
     /// template: 对照模板(文件/目录)
     fn add_new<'a>(&mut self, missing: &SimpleFile, contrast: &File) -> Result<()> {
-        if missing.is_dir() != contrast.is_dir() {
+        if missing.is_dir() != (contrast.is_dir() && !self.walk_filter.preserves(contrast)) {
             return Err(Error::new(std::io::ErrorKind::InvalidData, "ambiguous file type"));
         }
 
@@ -121,7 +180,7 @@ impl FileComparer<'_> {
 
             if !self.differences.new_folders.contains(&folder) && folder != "." {
                 // 过滤文件
-                if self.filter(&folder) {
+                if self.filter(&folder, true) {
                     self.differences.new_folders.push(folder);
                 }
             }
@@ -132,24 +191,30 @@ impl FileComparer<'_> {
                 if m.is_dir() {
                     self.add_new(&m, &corresponding)?;
                 } else {
-                    let path = corresponding.relativized_by(&self.base_path);
-                    // 过滤文件
-                    if self.filter(&path) {
-                        self.differences.new_files.push(path.to_string())
-                    }
+                    self.add_new_file(m, &corresponding);
                 }
             }
-        } else if let Some(_missing) = missing.as_file() {
-            let path = contrast.relativized_by(&self.base_path);
-            // 过滤文件
-            if self.filter(&path) {
-                self.differences.new_files.push(path)
-            }
+        } else if missing.is_file() {
+            self.add_new_file(missing, contrast);
         }
 
         Ok(())
     }
 
+    /// 添加需要传输的单个文件，符号链接会被单独记录
+    fn add_new_file(&mut self, missing: &SimpleFile, contrast: &File) {
+        let path = contrast.relativized_by(&self.base_path);
+
+        // 过滤文件
+        if self.filter(&path, false) {
+            if missing.is_symlink() {
+                self.differences.new_symlinks.push(path);
+            } else {
+                self.differences.new_files.push(path);
+            }
+        }
+    }
+
     /// 添加需要删除的文件/目录
     /// 
     /// file: 删除的文件(文件/目录)<br/>
@@ -167,19 +232,19 @@ impl FileComparer<'_> {
                     let path = if path.starts_with("./") { &path[2..] } else { &path[..] };
 
                     // 过滤文件
-                    if self.filter(&path) {
+                    if self.filter(path, false) {
                         self.differences.old_files.push(path.to_string());
                     }
                 }
             }
 
             // 过滤文件
-            if self.filter(&path) {
+            if self.filter(path, true) {
                 self.differences.old_folders.push(path.to_string());
             }
         } else if let Some(_existing) = existing.as_file() {
             // 过滤文件
-            if self.filter(&path) {
+            if self.filter(path, false) {
                 self.differences.old_files.push(path.to_string());
             }
         }
@@ -187,11 +252,71 @@ impl FileComparer<'_> {
         Ok(())
     }
 
-    fn filter<'a>(&self, test: &str) -> bool {
-        self.filters.test_all(test, true)
+    fn filter(&self, test: &str, is_dir: bool) -> bool {
+        self.filters.test_path(test, is_dir)
+    }
+
+    /// 将删除和新增的文件中内容相同(hash和长度一致)的配对成移动操作
+    /// 
+    /// contrast: 用来对照的状态，旧文件的hash和长度从这里获取
+    pub fn find_moved_files(&mut self, contrast: &State) -> Result<()> {
+        let old_files = self.differences.old_files.iter().collect::<HashSet<&String>>();
+        let new_files = self.differences.new_files.iter().collect::<HashSet<&String>>();
+
+        // 同时出现在新旧列表中的文件是原地修改的文件，不参与配对
+        let mut candidates: HashMap<(String, u64), Vec<String>> = HashMap::new();
+        for old in &self.differences.old_files {
+            if new_files.contains(old) {
+                continue;
+            }
+
+            // 符号链接没有内容，不参与配对
+            if let Some(data) = contrast.files.get_file(old).and_then(|f| f.as_file()).filter(|f| f.link_target.is_none()) {
+                candidates.entry((data.hash.to_owned(), data.length)).or_default().push(old.to_owned());
+            }
+        }
+
+        if candidates.is_empty() {
+            return Ok(());
+        }
+
+        let lengths = candidates.keys().map(|(_, length)| *length).collect::<HashSet<u64>>();
+        let hash_cache = self.fallback_cache.as_ref().unwrap_or(self.hash_cache);
+        let mut moved_files: Vec<(String, String)> = Vec::new();
+
+        for new in &self.differences.new_files {
+            if old_files.contains(new) {
+                continue;
+            }
+
+            // 长度对不上的文件不需要计算hash
+            let length = self.base_path.append(new)?.length()?;
+            if !lengths.contains(&length) {
+                continue;
+            }
+
+            let hash = hash_cache.get_hash(new, self.debug_mode);
+            if let Some(from) = candidates.get_mut(&(hash, length)).and_then(|c| c.pop()) {
+                moved_files.push((from, new.to_owned()));
+            }
+        }
+
+        let moved_from = moved_files.iter().map(|(from, _)| from).collect::<HashSet<&String>>();
+        let moved_to = moved_files.iter().map(|(_, to)| to).collect::<HashSet<&String>>();
+        self.differences.old_files.retain(|f| !moved_from.contains(f));
+        self.differences.new_files.retain(|f| !moved_to.contains(f));
+        self.differences.moved_files = moved_files;
+
+        Ok(())
     }
 
     pub fn compare(&mut self, directory: &File, contrast: &State) -> Result<()> {
+        // hash算法不一致时，使用状态文件的算法进行对比，避免所有文件都被当成有变化
+        if contrast.hash_algorithm != self.hash_cache.algorithm() {
+            self.fallback_cache = Some(HashCache::new(&self.base_path, contrast.hash_algorithm));
+            self.rehash_files = Some(Vec::new());
+        }
+
         self.find_new_files(&SimpleFile::new_directory("no_name", contrast.clone().files.files), directory)?;
         self.find_old_files(&SimpleFile::new_directory("no_name", contrast.clone().files.files), directory)?;
 
@@ -212,4 +337,269 @@ impl FileComparer<'_> {
 //     fn deref_mut(&mut self) -> &mut Self::Target {
 //         &mut self.differences
 //     }
-// }
\ No newline at end of file
+// }
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::ErrorKind;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::hash_algorithm::HashAlgorithm;
+    use crate::test_utils::TempDir;
+    use crate::walk_filter::AttributeFilter;
+    use crate::walk_filter::SymlinkPolicy;
+
+    struct Fixture {
+        temp: TempDir,
+        dir: File,
+        hash_cache: HashCache,
+        filters: RuleFilter,
+        walk_filter: WalkFilter,
+    }
+
+    impl Fixture {
+        fn new(name: &str) -> Fixture {
+            Fixture::with_symlinks(name, SymlinkPolicy::Follow)
+        }
+
+        fn with_symlinks(name: &str, symlinks: SymlinkPolicy) -> Fixture {
+            let temp = TempDir::new(&format!("comparer-{}", name));
+            let dir = temp.dir.to_owned();
+            Fixture {
+                hash_cache: HashCache::new(&dir, HashAlgorithm::Sha1),
+                filters: RuleFilter::new(&Vec::new()).unwrap(),
+                walk_filter: WalkFilter::new(&dir, &[], &AttributeFilter::default(), symlinks),
+                dir,
+                temp,
+            }
+        }
+
+        fn write(&self, path: &str, contents: &str) {
+            self.temp.write(path, contents);
+        }
+
+        /// 记录当前所有文件的状态
+        fn state(&self, paths: &[&str]) -> State {
+            let mut state = State::new(HashAlgorithm::Sha1);
+            for path in paths {
+                state.add_file(path, &self.dir, &self.hash_cache, false);
+            }
+            state
+        }
+
+        fn compare(&self, state: &State, comparison: Comparison) -> FileComparer<'_> {
+            let compare_func = |remote: &FileData, local: &File, path: &str, comparison: Comparison, hash_cache: &HashCache, debug_mode: bool| {
+                comparison.is_unchanged(remote, local, path, hash_cache, Duration::ZERO, debug_mode)
+            };
+
+            let mut comparer = FileComparer::new(&self.dir, compare_func, &self.hash_cache, comparison, &self.filters, &self.walk_filter, false);
+            comparer.compare(&self.dir, state).unwrap();
+            comparer
+        }
+    }
+
+    #[cfg(unix)]
+    fn chmod(fixture: &Fixture, path: &str, mode: u32) {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(fixture.dir.append(path).unwrap().get_raw(), fs::Permissions::from_mode(mode)).unwrap();
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn records_mode_of_legacy_state_without_chmod() {
+        let fixture = Fixture::new("legacy-mode");
+        fixture.write("a.txt", "a");
+        chmod(&fixture, "a.txt", 0o755);
+
+        // 旧版本的状态文件中没有记录权限
+        let mut state = fixture.state(&["a.txt"]);
+        state.files.get_file_mut("a.txt").unwrap().as_file_mut().unwrap().mode = None;
+
+        let comparer = fixture.compare(&state, Comparison::Hash);
+        assert!(!comparer.differences.has_differences());
+        assert!(comparer.differences.chmod_files.is_empty());
+        assert_eq!(comparer.unrecorded_modes, vec![("a.txt".to_owned(), 0o755)]);
+
+        state.set_modes(&comparer.unrecorded_modes);
+        assert_eq!(state.files.get_file("a.txt").unwrap().as_file().unwrap().mode, Some(0o755));
+        assert!(fixture.compare(&state, Comparison::Hash).unrecorded_modes.is_empty());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn queues_chmod_for_changed_mode() {
+        let fixture = Fixture::new("changed-mode");
+        fixture.write("a.txt", "a");
+        chmod(&fixture, "a.txt", 0o644);
+        let state = fixture.state(&["a.txt"]);
+
+        chmod(&fixture, "a.txt", 0o755);
+
+        let comparer = fixture.compare(&state, Comparison::Hash);
+        assert_eq!(comparer.differences.chmod_files, vec!["a.txt".to_owned()]);
+        assert!(comparer.differences.new_files.is_empty());
+        assert!(comparer.unrecorded_modes.is_empty());
+    }
+
+    fn remove(fixture: &Fixture, path: &str) {
+        fs::remove_file(fixture.dir.append(path).unwrap().get_raw()).unwrap();
+    }
+
+    fn sorted(mut files: Vec<String>) -> Vec<String> {
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn detects_moved_files() {
+        let fixture = Fixture::new("moved");
+        fixture.write("a.txt", "hello");
+        fixture.write("b.txt", "other");
+        let state = fixture.state(&["a.txt", "b.txt"]);
+
+        remove(&fixture, "a.txt");
+        fixture.write("sub/c.txt", "hello");
+
+        let mut comparer = fixture.compare(&state, Comparison::Hash);
+        comparer.find_moved_files(&state).unwrap();
+        assert_eq!(comparer.differences.moved_files, vec![("a.txt".to_owned(), "sub/c.txt".to_owned())]);
+        assert!(comparer.differences.old_files.is_empty());
+        assert!(comparer.differences.new_files.is_empty());
+    }
+
+    #[test]
+    fn does_not_pair_files_with_different_contents() {
+        let fixture = Fixture::new("different");
+        fixture.write("a.txt", "hello");
+        let state = fixture.state(&["a.txt"]);
+
+        // 长度相同但内容不同
+        remove(&fixture, "a.txt");
+        fixture.write("c.txt", "hellp");
+
+        let mut comparer = fixture.compare(&state, Comparison::Hash);
+        comparer.find_moved_files(&state).unwrap();
+        assert!(comparer.differences.moved_files.is_empty());
+        assert_eq!(comparer.differences.old_files, vec!["a.txt".to_owned()]);
+        assert_eq!(comparer.differences.new_files, vec!["c.txt".to_owned()]);
+    }
+
+    #[test]
+    fn pairs_each_old_file_once() {
+        let fixture = Fixture::new("duplicates");
+        fixture.write("a.txt", "same");
+        fixture.write("b.txt", "same");
+        let state = fixture.state(&["a.txt", "b.txt"]);
+
+        remove(&fixture, "a.txt");
+        remove(&fixture, "b.txt");
+        fixture.write("c.txt", "same");
+        fixture.write("d.txt", "same");
+        fixture.write("e.txt", "same");
+
+        let mut comparer = fixture.compare(&state, Comparison::Hash);
+        comparer.find_moved_files(&state).unwrap();
+
+        let moved = &comparer.differences.moved_files;
+        assert_eq!(moved.len(), 2);
+        assert_eq!(sorted(moved.iter().map(|(from, _)| from.to_owned()).collect()), vec!["a.txt", "b.txt"]);
+        assert!(comparer.differences.old_files.is_empty());
+        assert_eq!(comparer.differences.new_files.len(), 1);
+        assert!(!moved.iter().any(|(_, to)| *to == comparer.differences.new_files[0]));
+    }
+
+    #[test]
+    fn does_not_pair_files_modified_in_place() {
+        let fixture = Fixture::new("in-place");
+        fixture.write("a.txt", "hello");
+        let state = fixture.state(&["a.txt"]);
+
+        // a.txt原地修改后，新增的b.txt内容和原来的a.txt一样，但a.txt并没有被删除
+        fixture.write("a.txt", "changed");
+        fixture.write("b.txt", "hello");
+
+        let mut comparer = fixture.compare(&state, Comparison::Hash);
+        comparer.find_moved_files(&state).unwrap();
+        assert!(comparer.differences.moved_files.is_empty());
+        assert_eq!(comparer.differences.old_files, vec!["a.txt".to_owned()]);
+        assert_eq!(sorted(comparer.differences.new_files.clone()), vec!["a.txt", "b.txt"]);
+    }
+
+    #[cfg(unix)]
+    fn symlink(fixture: &Fixture, target: &str, link: &str) {
+        std::os::unix::fs::symlink(target, fixture.dir.append(link).unwrap().get_raw()).unwrap();
+    }
+
+    /// 源目录: a/x.txt, a/loop -> ..(指向上级目录), b -> a, y.txt -> a/x.txt
+    #[cfg(unix)]
+    fn symlink_fixture(name: &str, symlinks: SymlinkPolicy) -> Fixture {
+        let fixture = Fixture::with_symlinks(name, symlinks);
+        fixture.write("a/x.txt", "x");
+        symlink(&fixture, "..", "a/loop");
+        symlink(&fixture, "a", "b");
+        symlink(&fixture, "a/x.txt", "y.txt");
+        fixture
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn follows_symlinks_but_skips_loops() {
+        let fixture = symlink_fixture("follow", SymlinkPolicy::Follow);
+        fixture.walk_filter.check_symlinks(&fixture.dir).unwrap();
+
+        let comparer = fixture.compare(&State::new(HashAlgorithm::Sha1), Comparison::Hash);
+        assert_eq!(sorted(comparer.differences.new_files.clone()), vec!["a/x.txt", "b/x.txt", "y.txt"]);
+        assert_eq!(sorted(comparer.differences.new_folders.clone()), vec!["a", "b"]);
+        assert!(comparer.differences.new_symlinks.is_empty());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn skips_symlinks() {
+        let fixture = symlink_fixture("skip", SymlinkPolicy::Skip);
+        fixture.walk_filter.check_symlinks(&fixture.dir).unwrap();
+
+        let comparer = fixture.compare(&State::new(HashAlgorithm::Sha1), Comparison::Hash);
+        assert_eq!(comparer.differences.new_files, vec!["a/x.txt".to_owned()]);
+        assert_eq!(comparer.differences.new_folders, vec!["a".to_owned()]);
+        assert!(comparer.differences.new_symlinks.is_empty());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn preserves_symlinks() {
+        let fixture = symlink_fixture("preserve", SymlinkPolicy::Preserve);
+        fixture.walk_filter.check_symlinks(&fixture.dir).unwrap();
+
+        let comparer = fixture.compare(&State::new(HashAlgorithm::Sha1), Comparison::Hash);
+        assert_eq!(comparer.differences.new_files, vec!["a/x.txt".to_owned()]);
+        assert_eq!(sorted(comparer.differences.new_symlinks.clone()), vec!["a/loop", "b", "y.txt"]);
+
+        // 链接本身和指向的路径被记录到状态里
+        let scanned = SimpleFile::from_real_directory(&fixture.dir, None, Some(&fixture.walk_filter)).unwrap();
+        let link = scanned.as_dir().unwrap().get_file("a/loop").unwrap().as_file().unwrap();
+        assert_eq!(link.link_target.as_deref(), Some(".."));
+
+        let mut state = fixture.state(&["a/x.txt"]);
+        state.add_symlink("a/loop", "..");
+        state.add_symlink("b", "a");
+        state.add_symlink("y.txt", "a/x.txt");
+        assert!(!fixture.compare(&state, Comparison::Hash).differences.has_differences());
+
+        // 指向的路径变化后重新上传链接
+        fs::remove_file(fixture.dir.append("b").unwrap().get_raw()).unwrap();
+        symlink(&fixture, "a/x.txt", "b");
+        let comparer = fixture.compare(&state, Comparison::Hash);
+        assert_eq!(comparer.differences.new_symlinks, vec!["b".to_owned()]);
+        assert_eq!(comparer.differences.old_files, vec!["b".to_owned()]);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn rejects_symlinks_in_error_mode() {
+        let fixture = symlink_fixture("error", SymlinkPolicy::Error);
+        let error = fixture.walk_filter.check_symlinks(&fixture.dir).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+}
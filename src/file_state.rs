@@ -1,19 +1,64 @@
+use std::collections::HashSet;
+use std::io::Error;
+use std::io::ErrorKind;
+use std::io::Result;
+use std::time::SystemTime;
+
 use json::JsonValue;
 use json::object;
 
+use crate::chunker::Chunk;
 use crate::file::File;
+use crate::hash_algorithm::HashAlgorithm;
 use crate::hash_cache::HashCache;
 use crate::simple_file::DirData;
 use crate::simple_file::SimpleFile;
 use crate::utils::get_basename;
 use crate::utils::get_dirname;
 
+/// 当前的状态文件格式版本<br/>
+/// 0: 纯数组，只使用sha1<br/>
+/// 1: 包含hash-algorithm和files的对象<br/>
+/// 2: 增加了format-version, tool-version, source-dir, created等信息
+pub const STATE_FORMAT_VERSION: u64 = 2;
+
 pub struct State {
+    pub hash_algorithm: HashAlgorithm,
     pub files: DirData
 }
 
 impl State {
-    pub fn from_json_array(directory: &JsonValue) -> State {
+    pub fn new(hash_algorithm: HashAlgorithm) -> State {
+        State { hash_algorithm, files: DirData::new(Vec::new()) }
+    }
+
+    /// 从状态文件内容创建状态，旧版本的状态文件会被自动迁移(保存时使用新的格式)
+    pub fn from_json(state: &JsonValue) -> Result<State> {
+        if state.is_array() {
+            return Ok(State::from_json_array(state, HashAlgorithm::Sha1));
+        }
+
+        if !state.is_object() || !state["files"].is_array() {
+            return Err(Error::new(ErrorKind::InvalidData, "the state file is neither an array nor an object with the field 'files'"));
+        }
+
+        // 没有format-version的对象为版本1
+        let version = state["format-version"].as_u64().unwrap_or(1);
+        if version > STATE_FORMAT_VERSION {
+            return Err(Error::new(ErrorKind::InvalidData, format!(
+                "the state file was written in format version {} by incremental-upload {}, but this version only supports up to {}",
+                version, state["tool-version"].as_str().unwrap_or("unknown"), STATE_FORMAT_VERSION
+            )));
+        }
+
+        let algorithm = state["hash-algorithm"].as_str().unwrap_or("sha1");
+        let algorithm = HashAlgorithm::from_name(algorithm)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, format!("unsupported hash algorithm in state file: {}", algorithm)))?;
+
+        Ok(State::from_json_array(&state["files"], algorithm))
+    }
+
+    pub fn from_json_array(directory: &JsonValue, hash_algorithm: HashAlgorithm) -> State {
         fn gen(directory: &JsonValue) -> Vec<SimpleFile> {
             let mut files: Vec<SimpleFile> = Vec::new();
             for f in directory.members() {
@@ -22,15 +67,25 @@ impl State {
                     if f.has_key("children") { 
                         let children = gen(&f["children"]);
                         files.push(SimpleFile::new_directory(name, children));
+                    } else if let Some(link_target) = f["link-target"].as_str() {
+                        files.push(SimpleFile::new_symlink(name, link_target));
                     } else {
                         let length = f["length"].as_u64();
                         let hash = f["hash"].as_str();
                         let modified = f["modified"].as_u64();
+                        let modified_ns = f["modified-ns"].as_u64();
+                        let mode = f["mode"].as_str().and_then(|m| u32::from_str_radix(m, 8).ok());
                         if length.is_some() && hash.is_some() && modified.is_some() {
                             let length = length.unwrap();
                             let hash = hash.unwrap();
                             let modified = modified.unwrap();
-                            files.push(SimpleFile::new_file(name, length, hash, modified));
+                            let mut file = SimpleFile::new_file(name, length, hash, modified, modified_ns, mode);
+                            if f["chunks"].is_array() {
+                                file.as_file_mut().unwrap().chunks = Some(f["chunks"].members()
+                                    .filter_map(|c| Some(Chunk { hash: c["hash"].as_str()?.to_owned(), length: c["length"].as_u64()? }))
+                                    .collect());
+                            }
+                            files.push(file);
                         }
                     }
                 }
@@ -38,7 +93,21 @@ impl State {
             files
         }
         
-        State { files: DirData::new(gen(directory)) }
+        State { hash_algorithm, files: DirData::new(gen(directory)) }
+    }
+
+    /// 生成状态文件的内容，source_dir为源目录的路径，只用于记录
+    pub fn to_json(&self, source_dir: &str) -> JsonValue {
+        let created = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs();
+
+        object! {
+            "format-version": STATE_FORMAT_VERSION,
+            "tool-version": env!("CARGO_PKG_VERSION"),
+            "hash-algorithm": self.hash_algorithm.name(),
+            "source-dir": source_dir,
+            created: created,
+            files: self.to_json_array(),
+        }
     }
 
     pub fn to_json_array(&self) -> JsonValue {
@@ -46,13 +115,32 @@ impl State {
             let mut array = JsonValue::new_array();
             for f in &dir.files {
                 let fname = f.name.to_owned();
-                if let Some(f) = f.as_file() {
+                if let Some(link_target) = f.as_file().and_then(|f| f.link_target.as_ref()) {
                     array.push(object! {
+                        name: fname,
+                        "link-target": link_target.to_owned(),
+                    }).unwrap();
+                } else if let Some(f) = f.as_file() {
+                    let mut item = object! {
                         name: fname,
                         length: f.length,
-                        hash: f.sha1.to_owned(),
+                        hash: f.hash.to_owned(),
                         modified: f.modified,
-                    }).unwrap();
+                    };
+                    if let Some(modified_ns) = f.modified_ns {
+                        item["modified-ns"] = modified_ns.into();
+                    }
+                    // 权限位使用8进制字符串保存，比如: 755
+                    if let Some(mode) = f.mode {
+                        item["mode"] = format!("{:o}", mode).into();
+                    }
+                    if let Some(chunks) = &f.chunks {
+                        item["chunks"] = chunks.iter()
+                            .map(|c| object! { hash: c.hash.to_owned(), length: c.length })
+                            .collect::<Vec<JsonValue>>()
+                            .into();
+                    }
+                    array.push(item).unwrap();
                 } else if let Some(f) = f.as_dir() {
                     array.push(object! {
                         name: fname,
@@ -72,38 +160,168 @@ impl State {
     }
 
     pub fn make_dir(&mut self, path: &str) {
-        let parent = get_dirname(path);
-        let filename = get_basename(path);
-
-        let dir: &mut DirData = if parent.is_some() {
-            self.files.get_file_mut(parent.unwrap()).unwrap().as_dir_mut().unwrap()
-        } else {
-            &mut self.files
-        };
-        
-        dir.files.push(SimpleFile::new_directory(filename, Vec::new()));
+        self.get_dir_mut(Some(path));
     }
 
     pub fn add_file(&mut self, path: &str, sourcedir: &File, hash_cache: &HashCache, debug_mode: bool) {
         let parent = get_dirname(path);
         let filename = get_basename(path);
 
-        let dir: &mut DirData = if parent.is_some() {
-            self.files.get_file_mut(parent.unwrap()).unwrap().as_dir_mut().unwrap()
-        } else {
-            &mut self.files
-        };
+        let dir = self.get_dir_mut(parent);
 
         let file = sourcedir.append(path).unwrap();
         let length = file.length().unwrap();
-        let sha1 = hash_cache.get_hash(path, debug_mode);
+        let hash = hash_cache.get_hash(path, debug_mode);
         let modified = file.modified().unwrap();
-        dir.files.push(SimpleFile::new_file(filename, length, &sha1, modified));
+        let modified_ns = file.modified_ns().unwrap();
+        let mode = file.mode().unwrap();
+
+        // 内容没有变化时保留原来的分块列表(比如只修改了权限)
+        let mut new_file = SimpleFile::new_file(filename, length, &hash, modified, Some(modified_ns), mode);
+        if let Some(old) = dir.files.iter().find(|f| f.name == filename).and_then(|f| f.as_file()) {
+            if old.hash == hash {
+                new_file.as_file_mut().unwrap().chunks = old.chunks.clone();
+            }
+        }
+
+        // 如果状态里已经有同名的记录，则以新的为准
+        dir.files.retain(|f| f.name != filename);
+        dir.files.push(new_file);
+    }
+
+    /// 记录文件的分块列表
+    pub fn set_chunks(&mut self, path: &str, chunks: Option<Vec<Chunk>>) {
+        if let Some(file) = self.files.get_file_mut(path).and_then(|f| f.as_file_mut()) {
+            file.chunks = chunks;
+        }
+    }
+
+    /// 所有已经上传过的分块的hash
+    pub fn chunk_hashes(&self) -> HashSet<String> {
+        fn collect(dir: &DirData, result: &mut HashSet<String>) {
+            for f in &dir.files {
+                if let Some(chunks) = f.as_file().and_then(|f| f.chunks.as_ref()) {
+                    result.extend(chunks.iter().map(|c| c.hash.to_owned()));
+                } else if let Some(d) = f.as_dir() {
+                    collect(d, result);
+                }
+            }
+        }
+
+        let mut result = HashSet::new();
+        collect(&self.files, &mut result);
+        result
+    }
+
+    /// 记录一个符号链接(preserve模式)
+    pub fn add_symlink(&mut self, path: &str, link_target: &str) {
+        let filename = get_basename(path);
+        let dir = self.get_dir_mut(get_dirname(path));
+
+        dir.files.retain(|f| f.name != filename);
+        dir.files.push(SimpleFile::new_symlink(filename, link_target));
+    }
+
+    /// 获取指定路径的目录，路径上不存在的目录会被自动创建<br/>
+    /// 在某些操作失败后继续执行时(keep-going)，父目录可能还没有被记录到状态里
+    fn get_dir_mut(&mut self, path: Option<&str>) -> &mut DirData {
+        let mut dir = &mut self.files;
+
+        if let Some(path) = path {
+            for name in path.split('/') {
+                let index = match dir.files.iter().position(|f| f.name == name && f.is_dir()) {
+                    Some(index) => index,
+                    None => {
+                        dir.files.retain(|f| f.name != name);
+                        dir.files.push(SimpleFile::new_directory(name, Vec::new()));
+                        dir.files.len() - 1
+                    }
+                };
+                dir = dir.files[index].as_dir_mut().unwrap();
+            }
+        }
+
+        dir
+    }
+
+    /// 记录文件的权限，用于旧版本的状态文件中没有记录权限的文件
+    pub fn set_modes(&mut self, modes: &[(String, u32)]) {
+        for (path, mode) in modes {
+            if let Some(file) = self.files.get_file_mut(path).and_then(|f| f.as_file_mut()) {
+                file.mode = Some(*mode);
+            }
+        }
+    }
+
+    /// 使用hash_cache的算法重新计算指定文件的hash，并切换状态的hash算法
+    pub fn rehash(&mut self, paths: &[String], hash_cache: &HashCache, debug_mode: bool) {
+        for path in paths {
+            if let Some(file) = self.files.get_file_mut(path).and_then(|f| f.as_file_mut()) {
+                file.hash = hash_cache.get_hash(path, debug_mode);
+            }
+        }
+
+        self.hash_algorithm = hash_cache.algorithm();
     }
 }
 
 impl Clone for State {
     fn clone(&self) -> Self {
-        Self { files: self.files.clone() }
+        Self { hash_algorithm: self.hash_algorithm, files: self.files.clone() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn files() -> JsonValue {
+        json::parse(r#"[
+            {"name": "a.txt", "length": 1, "hash": "abc", "modified": 10},
+            {"name": "sub", "children": [
+                {"name": "b.txt", "length": 2, "hash": "def", "modified": 20, "modified-ns": 20000000005, "mode": "755"}
+            ]}
+        ]"#).unwrap()
+    }
+
+    #[test]
+    fn migrates_bare_array_as_sha1() {
+        let state = State::from_json(&files()).unwrap();
+        assert_eq!(state.hash_algorithm.name(), "sha1");
+        assert_eq!(state.files.get_file("a.txt").unwrap().as_file().unwrap().hash, "abc");
+        assert!(state.files.contains_file("sub/b.txt"));
+    }
+
+    #[test]
+    fn migrates_object_without_format_version() {
+        let state = State::from_json(&object! { "hash-algorithm": "blake3", files: files() }).unwrap();
+        assert_eq!(state.hash_algorithm.name(), "blake3");
+        assert!(state.files.contains_file("sub/b.txt"));
+    }
+
+    #[test]
+    fn rejects_newer_format_version() {
+        let state = object! { "format-version": STATE_FORMAT_VERSION + 1, "tool-version": "9.9.9", files: files() };
+        let error = State::from_json(&state).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        assert!(error.to_string().contains("9.9.9"));
+    }
+
+    #[test]
+    fn rejects_unknown_contents() {
+        assert!(State::from_json(&object! { files: "nope" }).is_err());
+        assert!(State::from_json(&object! { "hash-algorithm": "md5", files: files() }).is_err());
+    }
+
+    #[test]
+    fn round_trips_current_format() {
+        let state = State::from_json(&object! { "hash-algorithm": "sha256", files: files() }).unwrap();
+        let saved = state.to_json("/source");
+        assert_eq!(saved["format-version"], STATE_FORMAT_VERSION);
+        assert_eq!(saved["source-dir"], "/source");
+
+        let loaded = State::from_json(&json::parse(&saved.dump()).unwrap()).unwrap();
+        assert_eq!(loaded.hash_algorithm.name(), "sha256");
+        assert_eq!(loaded.to_json_array(), files());
     }
 }
@@ -0,0 +1,72 @@
+use hex::ToHex;
+use sha1::Digest;
+use sha1::Sha1;
+use sha2::Sha256;
+use xxhash_rust::xxh3::Xxh3;
+
+/// 文件hash算法
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum HashAlgorithm {
+    Sha1,
+    Sha256,
+    Blake3,
+    Xxh3,
+}
+
+impl HashAlgorithm {
+    pub fn from_name(name: &str) -> Option<HashAlgorithm> {
+        match &name.to_lowercase()[..] {
+            "sha1" => Some(HashAlgorithm::Sha1),
+            "sha256" => Some(HashAlgorithm::Sha256),
+            "blake3" => Some(HashAlgorithm::Blake3),
+            "xxh3" => Some(HashAlgorithm::Xxh3),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            HashAlgorithm::Sha1 => "sha1",
+            HashAlgorithm::Sha256 => "sha256",
+            HashAlgorithm::Blake3 => "blake3",
+            HashAlgorithm::Xxh3 => "xxh3",
+        }
+    }
+
+    pub fn hasher(&self) -> Hasher {
+        match self {
+            HashAlgorithm::Sha1 => Hasher::Sha1(Sha1::new()),
+            HashAlgorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+            HashAlgorithm::Blake3 => Hasher::Blake3(Box::new(blake3::Hasher::new())),
+            HashAlgorithm::Xxh3 => Hasher::Xxh3(Box::new(Xxh3::new())),
+        }
+    }
+}
+
+/// 流式计算hash，结果统一输出为小写的16进制字符串
+pub enum Hasher {
+    Sha1(Sha1),
+    Sha256(Sha256),
+    Blake3(Box<blake3::Hasher>),
+    Xxh3(Box<Xxh3>),
+}
+
+impl Hasher {
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Sha1(h) => h.update(data),
+            Hasher::Sha256(h) => h.update(data),
+            Hasher::Blake3(h) => { h.update(data); },
+            Hasher::Xxh3(h) => h.update(data),
+        }
+    }
+
+    pub fn finalize(self) -> String {
+        match self {
+            Hasher::Sha1(h) => h.finalize().encode_hex::<String>(),
+            Hasher::Sha256(h) => h.finalize().encode_hex::<String>(),
+            Hasher::Blake3(h) => h.finalize().to_hex().to_string(),
+            Hasher::Xxh3(h) => format!("{:016x}", h.digest()),
+        }
+    }
+}
\ No newline at end of file
@@ -1,36 +1,264 @@
 use std::cell::Cell;
 use std::collections::HashMap;
+use std::io::Result;
 use std::sync::Arc;
 use std::sync::Mutex;
 
+use json::JsonValue;
+use json::object;
+
 use crate::file::File;
+use crate::hash_algorithm::HashAlgorithm;
+use crate::progress;
+
+/// 单个文件的hash缓存项，文件长度和修改时间用来判断缓存是否仍然有效
+pub struct HashCacheEntry {
+    pub length: u64,
+    pub modified: u64,
+    /// 纳秒精度的修改时间，旧版本的缓存文件中没有
+    pub modified_ns: Option<u64>,
+    pub hash: String,
+}
 
 pub struct HashCache {
     sourcedir: File,
-    cache: Arc<Mutex<Cell<HashMap<String, String>>>>
+    algorithm: HashAlgorithm,
+    cache: Arc<Mutex<Cell<HashMap<String, String>>>>,
+    persisted: Arc<Mutex<Cell<HashMap<String, HashCacheEntry>>>>,
 }
 
 impl HashCache {
-    pub fn new(sourcedir: &File) -> HashCache {
-        HashCache { sourcedir: sourcedir.to_owned(), cache: Arc::new(Mutex::new(Cell::new(HashMap::new()))) }
+    pub fn new(sourcedir: &File, algorithm: HashAlgorithm) -> HashCache {
+        HashCache {
+            sourcedir: sourcedir.to_owned(),
+            algorithm,
+            cache: Arc::new(Mutex::new(Cell::new(HashMap::new()))),
+            persisted: Arc::new(Mutex::new(Cell::new(HashMap::new()))),
+        }
+    }
+
+    pub fn algorithm(&self) -> HashAlgorithm {
+        self.algorithm
     }
 
     pub fn get_hash(&self, relative_path: &str, debug_mode: bool) -> String {
         let mut map = self.cache.lock().unwrap();
         let map = map.get_mut();
-        
+
         if !map.contains_key(relative_path) {
             let file = self.sourcedir.append(relative_path).unwrap();
+            let hash = match self.get_persisted_hash(&file, relative_path) {
+                Some(hash) => {
+                    if debug_mode {
+                        progress!("hash cache hit (persisted): {}", relative_path);
+                    }
+                    hash
+                },
+                None => {
+                    if debug_mode {
+                        progress!("hash cache miss: {}", relative_path);
+                    }
+                    let hash = file.hash(self.algorithm).unwrap();
+                    self.update_persisted_hash(&file, relative_path, &hash);
+                    hash
+                }
+            };
+            map.insert(relative_path.to_owned(), hash);
+        } else {
             if debug_mode {
-                println!("hash cache miss: {}", relative_path);
+                progress!("hash cache hit: {}", relative_path);
             }
-            map.insert(relative_path.to_owned(), file.sha1().unwrap());
+        }
+
+        map.get(relative_path).unwrap().to_owned()
+    }
+
+    /// 从持久化缓存中查找hash，只有文件长度和修改时间都没有变化时才会使用
+    fn get_persisted_hash(&self, file: &File, relative_path: &str) -> Option<String> {
+        let mut persisted = self.persisted.lock().unwrap();
+        let entry = persisted.get_mut().get_mut(relative_path)?;
+
+        let length = file.length().ok()?;
+        let modified_ns = file.modified_ns().ok()?;
+
+        // 旧版本的缓存项只能按秒对比，命中后记录纳秒精度的修改时间
+        let unchanged = match entry.modified_ns {
+            Some(ns) => ns == modified_ns,
+            None => entry.modified == modified_ns / 1_000_000_000,
+        };
+
+        if entry.length == length && unchanged {
+            entry.modified_ns = Some(modified_ns);
+            Some(entry.hash.to_owned())
+        } else {
+            None
+        }
+    }
+
+    fn update_persisted_hash(&self, file: &File, relative_path: &str, hash: &str) {
+        let length = file.length();
+        let modified_ns = file.modified_ns();
+
+        let mut persisted = self.persisted.lock().unwrap();
+        let persisted = persisted.get_mut();
+
+        if let (Ok(length), Ok(modified_ns)) = (length, modified_ns) {
+            let modified = modified_ns / 1_000_000_000;
+            persisted.insert(relative_path.to_owned(), HashCacheEntry { length, modified, modified_ns: Some(modified_ns), hash: hash.to_owned() });
         } else {
+            persisted.remove(relative_path);
+        }
+    }
+
+    /// 从缓存文件加载持久化的hash缓存，缓存文件不存在或者无法解析时会被忽略
+    pub fn load_from_file(&self, cache_file: &File, debug_mode: bool) -> Result<()> {
+        if !cache_file.exists() {
+            return Ok(());
+        }
+
+        let contents = match json::parse(&cache_file.read()?) {
+            Ok(contents) => contents,
+            Err(e) => {
+                progress!("hash缓存文件无法解析，已忽略: {} ({})", cache_file.path(), e);
+                return Ok(());
+            }
+        };
+
+        // 使用其它hash算法生成的缓存无法复用(未记录算法的缓存文件均为sha1)
+        let algorithm = contents["hash-algorithm"].as_str().map_or(Some(HashAlgorithm::Sha1), HashAlgorithm::from_name);
+        if algorithm != Some(self.algorithm) {
             if debug_mode {
-                println!("hash cache hit: {}", relative_path);
+                progress!("hash cache file was generated by another hash algorithm, ignored: {}", cache_file.path());
             }
+            return Ok(());
         }
 
-        map.get(relative_path).unwrap().to_owned()
+        let mut persisted = self.persisted.lock().unwrap();
+        let persisted = persisted.get_mut();
+
+        for (path, entry) in contents["files"].entries() {
+            let length = entry["length"].as_u64();
+            let modified = entry["modified"].as_u64();
+            let modified_ns = entry["modified-ns"].as_u64();
+            let hash = entry["hash"].as_str();
+
+            if let (Some(length), Some(modified), Some(hash)) = (length, modified, hash) {
+                persisted.insert(path.to_owned(), HashCacheEntry { length, modified, modified_ns, hash: hash.to_owned() });
+            }
+        }
+
+        if debug_mode {
+            progress!("loaded {} entries from hash cache file: {}", persisted.len(), cache_file.path());
+        }
+
+        Ok(())
+    }
+
+    /// 将hash缓存写入缓存文件，已经不存在的文件会被剔除
+    pub fn save_to_file(&self, cache_file: &File) -> Result<()> {
+        let mut persisted = self.persisted.lock().unwrap();
+        let persisted = persisted.get_mut();
+
+        let mut files = JsonValue::new_object();
+        for (path, entry) in persisted.iter() {
+            if !self.sourcedir.append(path)?.is_file() {
+                continue;
+            }
+
+            files[&path[..]] = object! {
+                length: entry.length,
+                modified: entry.modified,
+                hash: entry.hash.to_owned(),
+            };
+            if let Some(modified_ns) = entry.modified_ns {
+                files[&path[..]]["modified-ns"] = modified_ns.into();
+            }
+        }
+
+        if let Some(parent) = cache_file.parent()? {
+            parent.mkdirs()?;
+        }
+        cache_file.write_atomically(object! { "hash-algorithm": self.algorithm.name(), files: files }.dump().as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::test_utils::TempDir;
+    use crate::test_utils::set_mtime;
+
+    const MODIFIED: Duration = Duration::from_secs(1_700_000_000);
+
+    struct Fixture {
+        temp: TempDir,
+        dir: File,
+        cache_file: File,
+    }
+
+    impl Fixture {
+        fn new(name: &str) -> Fixture {
+            let temp = TempDir::new(&format!("hash-cache-{}", name));
+            let dir = temp.file("source");
+            dir.mkdirs().unwrap();
+
+            Fixture { dir, cache_file: temp.file("cache.json"), temp }
+        }
+
+        /// 写入文件内容并设置修改时间
+        fn write(&self, contents: &str, modified: Duration) {
+            set_mtime(&self.temp.write("source/a.txt", contents), modified);
+        }
+
+        /// 使用一个新的缓存(模拟下一次运行)获取hash，之后保存缓存文件
+        fn hash(&self) -> String {
+            let cache = HashCache::new(&self.dir, HashAlgorithm::Sha1);
+            cache.load_from_file(&self.cache_file, false).unwrap();
+            let hash = cache.get_hash("a.txt", false);
+            cache.save_to_file(&self.cache_file).unwrap();
+            hash
+        }
+
+        fn real_hash(&self) -> String {
+            self.dir.append("a.txt").unwrap().hash(HashAlgorithm::Sha1).unwrap()
+        }
+    }
+
+    #[test]
+    fn reuses_hash_while_length_and_mtime_are_unchanged() {
+        let fixture = Fixture::new("reuse");
+        fixture.write("aaa", MODIFIED);
+        let hash = fixture.hash();
+
+        // 内容变化但长度和修改时间不变时使用缓存的hash
+        fixture.write("bbb", MODIFIED);
+        assert_eq!(fixture.hash(), hash);
+    }
+
+    #[test]
+    fn rehashes_file_rewritten_within_the_same_second() {
+        let fixture = Fixture::new("same-second");
+        fixture.write("aaa", MODIFIED);
+        let hash = fixture.hash();
+
+        fixture.write("bbb", MODIFIED + Duration::from_millis(1));
+        assert_ne!(fixture.hash(), hash);
+        assert_eq!(fixture.hash(), fixture.real_hash());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn upgrades_legacy_entries_in_seconds() {
+        let fixture = Fixture::new("legacy");
+        fixture.write("aaa", MODIFIED + Duration::from_millis(500));
+        fs::write(fixture.cache_file.get_raw(), object! {
+            files: { "a.txt": { length: 3, modified: MODIFIED.as_secs(), hash: "legacy" } }
+        }.dump()).unwrap();
+
+        assert_eq!(fixture.hash(), "legacy");
+        let saved = json::parse(&fixture.cache_file.read().unwrap()).unwrap();
+        assert_eq!(saved["files"]["a.txt"]["modified-ns"], (MODIFIED + Duration::from_millis(500)).as_nanos() as u64);
+    }
+}
@@ -0,0 +1,63 @@
+use std::io::Error;
+use std::io::ErrorKind;
+use std::io::Result;
+use std::process;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+use crate::progress;
+use crate::state_lock::StateLock;
+
+/// 被SIGINT/SIGTERM中断时的退出码(128 + SIGINT)
+pub const EXIT_CODE: i32 = 130;
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// 注册SIGINT(Ctrl-C)和SIGTERM的处理函数<br/>
+/// 第一次收到信号时只设置中断标记，不再派发新的任务，等待正在执行的命令结束后保存状态并退出<br/>
+/// 第二次收到信号时删除本地锁文件后立即退出，不保存状态
+pub fn install() -> std::result::Result<(), ctrlc::Error> {
+    ctrlc::set_handler(|| {
+        if INTERRUPTED.swap(true, Ordering::SeqCst) {
+            progress!("\n再次收到中断信号，立即退出");
+            StateLock::release_held();
+            process::exit(EXIT_CODE);
+        }
+
+        progress!("\n收到中断信号，等待正在执行的任务结束后保存状态并退出(再次按下Ctrl-C立即退出)...");
+    })
+}
+
+pub fn is_interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// 等待一段时间，期间收到中断信号时提前返回false
+pub fn sleep(duration: Duration) -> bool {
+    let deadline = Instant::now() + duration;
+
+    loop {
+        if is_interrupted() {
+            return false;
+        }
+
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return true;
+        }
+
+        thread::sleep(remaining.min(Duration::from_millis(100)));
+    }
+}
+
+/// 已经收到中断信号时返回错误，用于在执行下一个操作之前检查
+pub fn check() -> Result<()> {
+    if is_interrupted() {
+        Err(Error::new(ErrorKind::Interrupted, "interrupted by signal"))
+    } else {
+        Ok(())
+    }
+}
@@ -9,8 +9,27 @@ pub mod utils;
 pub mod variable_replace;
 pub mod simple_file;
 pub mod file_state;
+pub mod state_compression;
+pub mod checkpoint;
+pub mod interrupt;
+pub mod state_lock;
 pub mod differences;
 pub mod hash_cache;
+pub mod hash_algorithm;
+pub mod comparison;
+pub mod chunker;
 pub mod rule_filter;
+pub mod walk_filter;
+pub mod retry_policy;
+pub mod failure_report;
+pub mod backend;
+pub mod local_backend;
+pub mod s3_backend;
+pub mod sftp_backend;
+pub mod plan;
+pub mod route;
+
+#[cfg(test)]
+mod test_utils;
 
 pub type AppResult<R> = std::result::Result<R, Box<dyn std::error::Error>>;
\ No newline at end of file
@@ -0,0 +1,110 @@
+use std::io::Result;
+
+use crate::backend::Backend;
+use crate::file::File;
+
+/// 把文件同步到本地的另一个目录(也可以是挂载的网络共享目录)
+pub struct LocalBackend {
+    target_dir: File,
+}
+
+impl LocalBackend {
+    pub fn new(target_dir: &File) -> LocalBackend {
+        LocalBackend { target_dir: target_dir.clone() }
+    }
+
+    /// 获取目标文件，并确保其父目录存在，已存在的目标文件会被删除
+    fn prepare_destination(&self, path: &str) -> Result<File> {
+        let dest = self.target_dir.append(path)?;
+
+        if let Some(parent) = dest.parent()? {
+            parent.mkdirs()?;
+        }
+
+        if dest.exists() || dest.is_symlink() {
+            dest.rm()?;
+        }
+
+        Ok(dest)
+    }
+}
+
+impl Backend for LocalBackend {
+    fn upload_file(&self, local: &File, path: &str) -> Result<()> {
+        let dest = self.prepare_destination(path)?;
+        local.cp(&dest.path())
+    }
+
+    fn delete_file(&self, path: &str) -> Result<()> {
+        let file = self.target_dir.append(path)?;
+        if file.exists() || file.is_symlink() {
+            file.rm()?;
+        }
+
+        Ok(())
+    }
+
+    fn make_dir(&self, path: &str) -> Result<()> {
+        self.target_dir.append(path)?.mkdirs()
+    }
+
+    fn delete_dir(&self, path: &str) -> Result<()> {
+        let dir = self.target_dir.append(path)?;
+        if dir.exists() {
+            dir.rm()?;
+        }
+
+        Ok(())
+    }
+
+    fn move_file(&self, from: &str, to: &str) -> Result<()> {
+        let from = self.target_dir.append(from)?;
+        let dest = self.prepare_destination(to)?;
+        from.mv(&dest.path())
+    }
+
+    fn chmod_file(&self, path: &str, mode: u32) -> Result<()> {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(self.target_dir.append(path)?.get_raw(), std::fs::Permissions::from_mode(mode))?;
+        }
+
+        #[cfg(not(unix))]
+        let _ = (path, mode);
+
+        Ok(())
+    }
+
+    fn upload_symlink(&self, target: &str, path: &str) -> Result<()> {
+        let dest = self.prepare_destination(path)?;
+
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(target, dest.get_raw())?;
+
+        #[cfg(windows)]
+        std::os::windows::fs::symlink_file(target, dest.get_raw())?;
+
+        Ok(())
+    }
+
+    fn upload_state(&self, local: &File, name: &str) -> Result<()> {
+        self.upload_file(local, name)
+    }
+
+    fn download_state(&self, name: &str, local: &File) -> Result<bool> {
+        let state = self.target_dir.append(name)?;
+        if !state.is_file() {
+            return Ok(false);
+        }
+
+        if let Some(parent) = local.parent()? {
+            parent.mkdirs()?;
+        }
+
+        // 原子地替换本地状态文件，复制中断时不会留下不完整的状态文件
+        local.write_atomically(&std::fs::read(state.get_raw())?)?;
+
+        Ok(true)
+    }
+}
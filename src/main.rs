@@ -1,9 +1,13 @@
+use std::io::Error;
+use std::io::ErrorKind;
 use std::panic;
 use std::process;
 
 use backtrace::Backtrace;
 use incremental_upload::AppResult;
 use incremental_upload::application::App;
+use incremental_upload::interrupt;
+use incremental_upload::progress;
 
 fn run() -> AppResult<()> {
     App::new()?.main()
@@ -27,5 +31,23 @@ fn main() {
         process::exit(1);
     }));
 
-    run().unwrap();
+    if let Err(e) = interrupt::install() {
+        progress!("警告: 无法注册中断信号的处理函数: {}", e);
+    }
+
+    let result = run();
+
+    // 被中断时使用单独的退出码，已完成的部分已经保存到状态文件里了
+    // 中断后发生的其它错误(比如保存状态文件失败)仍然需要输出
+    if interrupt::is_interrupted() {
+        if let Err(e) = &result {
+            if !e.downcast_ref::<Error>().is_some_and(|e| e.kind() == ErrorKind::Interrupted) {
+                progress!("中断时发生错误: {}", e);
+            }
+        }
+        progress!("已中断");
+        process::exit(interrupt::EXIT_CODE);
+    }
+
+    result.unwrap();
 }
\ No newline at end of file
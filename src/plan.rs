@@ -0,0 +1,229 @@
+use std::io::Error;
+use std::io::ErrorKind;
+use std::io::Result;
+
+use json::JsonValue;
+use json::object;
+use yaml_rust::Yaml;
+use yaml_rust::YamlEmitter;
+
+use crate::differences::Differences;
+use crate::hash_algorithm::HashAlgorithm;
+
+/// 计划中的一个操作
+#[derive(PartialEq, Eq, Debug)]
+pub struct PlannedOperation {
+    /// 操作类型: start-up, delete-file, make-dir, move-file, delete-dir, upload-file, chmod-file, upload-symlink, clean-up
+    pub action: String,
+    /// 文件路径，start-up和clean-up没有路径
+    pub path: String,
+    /// 移动文件的原路径
+    pub from: Option<String>,
+    pub size: Option<u64>,
+    pub hash: Option<String>,
+    /// 符号链接指向的路径(upload-symlink)
+    pub link_target: Option<String>,
+    /// 文件的权限位(8进制，比如: 755)
+    pub mode: Option<String>,
+    /// 会被执行的命令行(已经替换过变量)，使用内置后端时为空
+    pub commands: Vec<Vec<String>>,
+}
+
+/// 按照执行顺序排列的所有操作，用于在实际执行之前进行预览
+pub struct Plan {
+    /// 使用的内置后端，为空时使用commands下的命令
+    pub backend: String,
+    pub hash_algorithm: HashAlgorithm,
+    /// 分块上传时存放分块和清单的临时目录(命令行中的$chunk-file和$manifest位于此目录下)，执行计划时使用同一个目录
+    pub temp_dir: Option<String>,
+    pub operations: Vec<PlannedOperation>,
+}
+
+impl Plan {
+    pub fn new(backend: &str, hash_algorithm: HashAlgorithm) -> Plan {
+        Plan { backend: backend.to_owned(), hash_algorithm, temp_dir: None, operations: Vec::new() }
+    }
+
+    /// 从to_json()输出的内容中读取计划
+    pub fn from_json(plan: &JsonValue) -> Result<Plan> {
+        let invalid = |message: &str| Error::new(ErrorKind::InvalidData, format!("invalid plan file: {}", message));
+
+        let backend = plan["backend"].as_str().ok_or_else(|| invalid("missing 'backend'"))?;
+        let backend = if backend == "commands" { "" } else { backend };
+        let algorithm = plan["hash-algorithm"].as_str().ok_or_else(|| invalid("missing 'hash-algorithm'"))?;
+        let algorithm = HashAlgorithm::from_name(algorithm).ok_or_else(|| invalid(&format!("unsupported hash algorithm: {}", algorithm)))?;
+
+        if !plan["operations"].is_array() {
+            return Err(invalid("missing 'operations'"));
+        }
+
+        let mut result = Plan::new(backend, algorithm);
+        result.temp_dir = plan["temp-dir"].as_str().map(|v| v.to_owned());
+
+        for op in plan["operations"].members() {
+            let action = op["action"].as_str().ok_or_else(|| invalid("missing 'action' in operation"))?;
+            let commands = op["commands"].members()
+                .map(|c| c.members().map(|v| v.as_str().unwrap_or("").to_owned()).collect())
+                .collect();
+
+            result.operations.push(PlannedOperation {
+                action: action.to_owned(),
+                path: op["path"].as_str().unwrap_or("").to_owned(),
+                from: op["from"].as_str().map(|v| v.to_owned()),
+                size: op["size"].as_u64(),
+                hash: op["hash"].as_str().map(|v| v.to_owned()),
+                link_target: op["link-target"].as_str().map(|v| v.to_owned()),
+                mode: op["mode"].as_str().map(|v| v.to_owned()),
+                commands,
+            });
+        }
+
+        Ok(result)
+    }
+
+    /// 计划中包含的文件差异
+    pub fn to_differences(&self) -> Differences {
+        let mut diff = Differences::new();
+
+        for op in &self.operations {
+            let path = op.path.to_owned();
+            match &op.action[..] {
+                "delete-file" => diff.old_files.push(path),
+                "make-dir" => diff.new_folders.push(path),
+                "move-file" => diff.moved_files.push((op.from.to_owned().unwrap_or_default(), path)),
+                "delete-dir" => diff.old_folders.push(path),
+                "upload-file" => diff.new_files.push(path),
+                "chmod-file" => diff.chmod_files.push(path),
+                "upload-symlink" => diff.new_symlinks.push(path),
+                _ => (),
+            }
+        }
+
+        diff
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        let mut operations = JsonValue::new_array();
+
+        for op in &self.operations {
+            let mut item = object! { action: op.action.to_owned() };
+
+            if !op.path.is_empty() {
+                item["path"] = op.path.to_owned().into();
+            }
+            if let Some(from) = &op.from {
+                item["from"] = from.to_owned().into();
+            }
+            if let Some(size) = op.size {
+                item["size"] = size.into();
+            }
+            if let Some(hash) = &op.hash {
+                item["hash"] = hash.to_owned().into();
+            }
+            if let Some(link_target) = &op.link_target {
+                item["link-target"] = link_target.to_owned().into();
+            }
+            if let Some(mode) = &op.mode {
+                item["mode"] = mode.to_owned().into();
+            }
+
+            item["commands"] = op.commands.iter()
+                .map(|c| JsonValue::from(c.clone()))
+                .collect::<Vec<JsonValue>>()
+                .into();
+
+            operations.push(item).unwrap();
+        }
+
+        let mut plan = object! {
+            backend: if self.backend.is_empty() { "commands" } else { &self.backend[..] },
+            "hash-algorithm": self.hash_algorithm.name(),
+        };
+        if let Some(temp_dir) = &self.temp_dir {
+            plan["temp-dir"] = temp_dir.to_owned().into();
+        }
+        plan["operations"] = operations;
+
+        plan
+    }
+
+    pub fn to_yaml(&self) -> String {
+        fn convert(value: &JsonValue) -> Yaml {
+            match value {
+                JsonValue::Object(object) => {
+                    let mut hash = yaml_rust::yaml::Hash::new();
+                    for (k, v) in object.iter() {
+                        hash.insert(Yaml::String(k.to_owned()), convert(v));
+                    }
+                    Yaml::Hash(hash)
+                },
+                JsonValue::Array(array) => Yaml::Array(array.iter().map(convert).collect()),
+                JsonValue::Number(_) => Yaml::Integer(value.as_i64().unwrap_or(0)),
+                JsonValue::Boolean(b) => Yaml::Boolean(*b),
+                JsonValue::Null => Yaml::Null,
+                _ => Yaml::String(value.as_str().unwrap_or("").to_owned()),
+            }
+        }
+
+        let mut output = String::new();
+        YamlEmitter::new(&mut output).dump(&convert(&self.to_json())).unwrap();
+        output
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut output = String::new();
+
+        for op in &self.operations {
+            let label = match &op.action[..] {
+                "start-up" => "初始化指令",
+                "delete-file" => "删除文件",
+                "make-dir" => "新目录",
+                "move-file" => "移动文件",
+                "delete-dir" => "删除目录",
+                "upload-file" => "新文件",
+                "chmod-file" => "修改权限",
+                "upload-symlink" => "符号链接",
+                "clean-up" => "清理指令",
+                other => other,
+            };
+
+            let mut line = label.to_owned();
+            if let Some(from) = &op.from {
+                line += &format!(": {} -> {}", from, op.path);
+            } else if let Some(link_target) = &op.link_target {
+                line += &format!(": {} -> {}", op.path, link_target);
+            } else if !op.path.is_empty() {
+                line += &format!(": {}", op.path);
+            }
+            if let (Some(size), Some(hash)) = (op.size, &op.hash) {
+                line += &format!(" ({} bytes, {}: {})", size, self.hash_algorithm.name(), hash);
+            } else if let Some(mode) = &op.mode {
+                line += &format!(" ({})", mode);
+            }
+
+            output += &line;
+            output += "\n";
+
+            for command in &op.commands {
+                output += &format!("    > {:?}\n", command);
+            }
+        }
+
+        let count = |action: &str| self.operations.iter().filter(|op| op.action == action).count();
+        output += &format!(
+            "旧文件: {}, 旧目录: {}, 新文件: {}, 新目录: {}, 移动文件: {}\n",
+            count("delete-file"), count("delete-dir"), count("upload-file"), count("make-dir"), count("move-file"),
+        );
+
+        output
+    }
+
+    /// 按照指定的格式(json, yaml, text)输出计划
+    pub fn render(&self, format: &str) -> String {
+        match format {
+            "json" => self.to_json().pretty(2),
+            "yaml" => self.to_yaml(),
+            _ => self.to_text(),
+        }
+    }
+}
@@ -0,0 +1,109 @@
+use std::time::Duration;
+
+use regex::Regex;
+
+use crate::subprocess_task::SubprocessResult;
+
+/// 命令执行失败时的重试策略
+#[derive(Clone)]
+pub struct RetryPolicy {
+    /// 最多执行的次数(包括第一次)，为1时不进行重试
+    pub attempts: u32,
+    /// 第一次重试前的等待时间，之后每次重试翻倍
+    pub backoff: Duration,
+    /// 重试等待时间的上限
+    pub max_backoff: Duration,
+    /// 只有返回码在此列表中时才会重试，为空时任何非0返回码都会重试
+    pub exit_codes: Vec<i32>,
+    /// 只有stderr匹配此正则表达式时才会重试
+    pub stderr_pattern: Option<Regex>,
+}
+
+impl RetryPolicy {
+    /// 不进行任何重试
+    pub fn none() -> RetryPolicy {
+        RetryPolicy {
+            attempts: 1,
+            backoff: Duration::ZERO,
+            max_backoff: Duration::ZERO,
+            exit_codes: Vec::new(),
+            stderr_pattern: None,
+        }
+    }
+
+    /// 判断失败的命令是否满足重试条件
+    pub fn should_retry(&self, result: &SubprocessResult) -> bool {
+        if !self.exit_codes.is_empty() && !self.exit_codes.contains(&result.exitcode) {
+            return false;
+        }
+
+        if let Some(pattern) = &self.stderr_pattern {
+            return pattern.is_match(&result.stderr);
+        }
+
+        true
+    }
+
+    /// 第attempt次执行失败后，进行下一次重试前需要等待的时间
+    pub fn backoff_for(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        self.backoff.saturating_mul(factor).min(self.max_backoff)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(backoff: u64, max_backoff: u64) -> RetryPolicy {
+        RetryPolicy {
+            attempts: 5,
+            backoff: Duration::from_secs(backoff),
+            max_backoff: Duration::from_secs(max_backoff),
+            exit_codes: Vec::new(),
+            stderr_pattern: None,
+        }
+    }
+
+    fn failed(exitcode: i32, stderr: &str) -> SubprocessResult {
+        SubprocessResult { stdout: String::new(), stderr: stderr.to_owned(), exitcode }
+    }
+
+    #[test]
+    fn backoff_doubles_after_each_attempt() {
+        let retry = policy(2, 60);
+        assert_eq!(retry.backoff_for(1), Duration::from_secs(2));
+        assert_eq!(retry.backoff_for(2), Duration::from_secs(4));
+        assert_eq!(retry.backoff_for(3), Duration::from_secs(8));
+    }
+
+    #[test]
+    fn backoff_is_capped_by_max_backoff() {
+        let retry = policy(2, 10);
+        assert_eq!(retry.backoff_for(4), Duration::from_secs(10));
+        assert_eq!(retry.backoff_for(100), Duration::from_secs(10));
+    }
+
+    #[test]
+    fn backoff_of_no_retry_is_zero() {
+        assert_eq!(RetryPolicy::none().backoff_for(1), Duration::ZERO);
+    }
+
+    #[test]
+    fn retries_only_listed_exit_codes() {
+        let mut retry = policy(1, 1);
+        assert!(retry.should_retry(&failed(1, "")));
+
+        retry.exit_codes = vec![255];
+        assert!(!retry.should_retry(&failed(1, "")));
+        assert!(retry.should_retry(&failed(255, "")));
+    }
+
+    #[test]
+    fn retries_only_matching_stderr() {
+        let mut retry = policy(1, 1);
+        retry.stderr_pattern = Some(Regex::new("timed out").unwrap());
+        assert!(retry.should_retry(&failed(1, "connection timed out")));
+        assert!(!retry.should_retry(&failed(1, "permission denied")));
+    }
+}
@@ -0,0 +1,118 @@
+use crate::AppResult;
+use crate::retry_policy::RetryPolicy;
+use crate::rule_filter::RuleFilter;
+
+/// 路由中可以单独配置命令的操作
+#[derive(Clone, Copy)]
+pub enum RouteAction {
+    UploadFile,
+    DeleteFile,
+}
+
+/// routes下的一条路由，匹配的文件使用这里的命令代替全局的命令
+#[derive(Clone)]
+pub struct RouteConfig {
+    /// 正则表达式规则，和全局的file-filters语法相同
+    pub file_filters: Vec<String>,
+    /// gitignore语法的规则，和全局的include, exclude语法相同
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    pub upload_file: Vec<Vec<String>>,
+    pub delete_file: Vec<Vec<String>>,
+    pub upload_file_retry: RetryPolicy,
+    pub delete_file_retry: RetryPolicy,
+}
+
+/// 编译好规则的路由
+pub struct Route {
+    pub config: RouteConfig,
+    filter: RuleFilter,
+}
+
+impl Route {
+    pub fn new(config: &RouteConfig) -> AppResult<Route> {
+        let filter = RuleFilter::new_with_globs(&config.file_filters, &config.include, &config.exclude)?;
+
+        Ok(Route { config: config.clone(), filter })
+    }
+
+    /// 判断文件是否匹配这条路由，没有任何规则的路由匹配所有文件
+    pub fn matches(&self, path: &str) -> bool {
+        self.filter.test_path(path, false)
+    }
+
+    /// 路由中操作对应的命令和重试策略
+    pub fn commands(&self, action: RouteAction) -> (&Vec<Vec<String>>, &RetryPolicy) {
+        match action {
+            RouteAction::UploadFile => (&self.config.upload_file, &self.config.upload_file_retry),
+            RouteAction::DeleteFile => (&self.config.delete_file, &self.config.delete_file_retry),
+        }
+    }
+}
+
+/// 按顺序查找第一条匹配path，并且配置了action对应命令的路由，返回路由的下标<br/>
+/// 没有配置某个命令的路由不参与这个操作的匹配，比如只配置了upload-file的路由不会影响删除文件
+pub fn find_route(routes: &[Route], action: RouteAction, path: &str) -> Option<usize> {
+    routes.iter().position(|r| !r.commands(action).0.is_empty() && r.matches(path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|v| v.to_string()).collect()
+    }
+
+    fn route(file_filters: &[&str], include: &[&str], exclude: &[&str], upload: &str, delete: &str) -> Route {
+        let command = |c: &str| if c.is_empty() { Vec::new() } else { vec![strings(&[c])] };
+        Route::new(&RouteConfig {
+            file_filters: strings(file_filters),
+            include: strings(include),
+            exclude: strings(exclude),
+            upload_file: command(upload),
+            delete_file: command(delete),
+            upload_file_retry: RetryPolicy::none(),
+            delete_file_retry: RetryPolicy::none(),
+        }).unwrap()
+    }
+
+    #[test]
+    fn first_matching_route_wins() {
+        let routes = vec![
+            route(&[], &["*.mp4"], &[], "upload-video", ""),
+            route(&[], &["media/"], &[], "upload-media", "delete-media"),
+            route(&[], &[], &[], "upload-default", ""),
+        ];
+
+        assert_eq!(find_route(&routes, RouteAction::UploadFile, "media/a.mp4"), Some(0));
+        assert_eq!(find_route(&routes, RouteAction::UploadFile, "media/a.jpg"), Some(1));
+        assert_eq!(find_route(&routes, RouteAction::UploadFile, "docs/a.txt"), Some(2));
+    }
+
+    #[test]
+    fn routes_without_the_command_are_skipped() {
+        let routes = vec![
+            route(&[], &["*.mp4"], &[], "upload-video", ""),
+            route(&[], &["media/"], &[], "upload-media", "delete-media"),
+        ];
+
+        assert_eq!(find_route(&routes, RouteAction::DeleteFile, "media/a.mp4"), Some(1));
+        assert_eq!(find_route(&routes, RouteAction::DeleteFile, "docs/a.mp4"), None);
+        assert_eq!(routes[1].commands(RouteAction::DeleteFile).0, &vec![strings(&["delete-media"])]);
+    }
+
+    #[test]
+    fn combines_regex_and_gitignore_rules() {
+        let routes = vec![route(&["^logs/"], &[], &["*.tmp"], "upload-logs", "")];
+
+        assert_eq!(find_route(&routes, RouteAction::UploadFile, "logs/a.log"), Some(0));
+        assert_eq!(find_route(&routes, RouteAction::UploadFile, "logs/a.tmp"), None);
+        assert_eq!(find_route(&routes, RouteAction::UploadFile, "data/a.log"), None);
+    }
+
+    #[test]
+    fn no_routes_match_nothing() {
+        assert_eq!(find_route(&[], RouteAction::UploadFile, "a.txt"), None);
+    }
+}
@@ -1,16 +1,32 @@
+use std::collections::HashMap;
 use std::io::Error;
 use std::io::ErrorKind;
 
+use ignore::gitignore::Gitignore;
+use ignore::gitignore::GitignoreBuilder;
 use regex::Regex;
 
 use crate::AppResult;
 
 pub struct RuleFilter {
-    pub filters: Vec<(Regex, bool)>
+    pub filters: Vec<(Regex, bool)>,
+    /// include和exclude按顺序合并成的一组gitignore规则，匹配的规则为白名单(!开头)时表示需要处理<br/>
+    /// include的规则在前并且取反(p变为!p，!p变为p)，exclude的规则在后保持不变
+    globs: Option<Gitignore>,
+    /// 每条规则最后出现的位置，用来比较文件本身和上级目录匹配到的规则的先后顺序
+    glob_order: HashMap<String, usize>,
+    /// include不为空时，没有匹配任何规则的文件不会被处理
+    include_only: bool,
 }
 
 impl RuleFilter {
     pub fn new(rules: &Vec<String>) -> AppResult<RuleFilter> {
+        RuleFilter::new_with_globs(rules, &[], &[])
+    }
+
+    /// rules: 正则表达式规则(file-filters)<br/>
+    /// include, exclude: gitignore语法的规则列表，按顺序匹配，以最后一条匹配的规则为准
+    pub fn new_with_globs(rules: &[String], include: &[String], exclude: &[String]) -> AppResult<RuleFilter> {
         // 预编译正则表达式
         let mut regexes_compiled = Vec::<(Regex, bool)>::new();
         for pattern in rules {
@@ -27,7 +43,74 @@ impl RuleFilter {
             regexes_compiled.push((pat.unwrap(), reversed));
         }
 
-        Ok(RuleFilter { filters: regexes_compiled })
+        let (globs, glob_order) = RuleFilter::build_globs(include, exclude)?;
+
+        Ok(RuleFilter { 
+            filters: regexes_compiled,
+            globs,
+            glob_order,
+            include_only: !include.is_empty(),
+        })
+    }
+
+    /// 把include和exclude的规则按顺序加入同一个GitignoreBuilder，这样后面的!规则可以重新包含前面排除的文件
+    fn build_globs(include: &[String], exclude: &[String]) -> AppResult<(Option<Gitignore>, HashMap<String, usize>)> {
+        let mut glob_order = HashMap::new();
+        if include.is_empty() && exclude.is_empty() {
+            return Ok((None, glob_order));
+        }
+
+        let include = include.iter().map(|rule| match rule.strip_prefix('!') {
+            Some(negated) => (negated.to_owned(), &rule[..], "include"),
+            None => (format!("!{}", rule), &rule[..], "include"),
+        });
+        let exclude = exclude.iter().map(|rule| (rule.to_owned(), &rule[..], "exclude"));
+
+        let mut builder = GitignoreBuilder::new("");
+        for (index, (line, rule, field)) in include.chain(exclude).enumerate() {
+            builder.add_line(None, &line)
+                .map_err(|e| Error::new(ErrorKind::InvalidInput, format!("invalid {} rule '{}': {}", field, rule, e)))?;
+            glob_order.insert(line, index);
+        }
+
+        let globs = builder.build()
+            .map_err(|e| Error::new(ErrorKind::InvalidInput, format!("invalid include/exclude rules: {}", e)))?;
+
+        Ok((Some(globs), glob_order))
+    }
+
+    /// 依次匹配文件本身和所有上级目录，以规则顺序中最后一条匹配的规则为准<br/>
+    /// 没有匹配任何规则时，include为空或者是目录时返回true
+    fn test_globs(&self, path: &str, is_dir: bool) -> bool {
+        let globs = match &self.globs {
+            Some(globs) => globs,
+            None => return true,
+        };
+
+        let mut last: Option<(usize, bool)> = None;
+        let mut current = Some((path, is_dir));
+
+        while let Some((path, is_dir)) = current {
+            if let Some(glob) = globs.matched(path, is_dir).inner() {
+                let order = self.glob_order.get(glob.original()).copied().unwrap_or(0);
+                if last.is_none_or(|(last, _)| order > last) {
+                    last = Some((order, glob.is_whitelist()));
+                }
+            }
+
+            current = path.rfind('/').map(|pos| (&path[..pos], true));
+        }
+
+        match last {
+            Some((_, whitelisted)) => whitelisted,
+            None => is_dir || !self.include_only,
+        }
+    }
+
+    /// 判断一个文件或者目录是否需要被处理，需要同时满足file-filters以及include, exclude<br/>
+    /// 目录只在明确匹配了规则时受include的限制，否则目录下被include的文件也无法被扫描到
+    pub fn test_path(&self, path: &str, is_dir: bool) -> bool {
+        self.test_all(path, true) && self.test_globs(path, is_dir)
     }
 
     pub fn test_any(&self, text: &str, if_empty: bool) -> bool {
@@ -45,4 +128,86 @@ impl RuleFilter {
 
         self.filters.iter().all(|(reg, reversed)| reg.is_match(text) != *reversed)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(include: &[&str], exclude: &[&str]) -> RuleFilter {
+        let rules = |r: &[&str]| r.iter().map(|v| v.to_string()).collect::<Vec<String>>();
+        RuleFilter::new_with_globs(&[], &rules(include), &rules(exclude)).unwrap()
+    }
+
+    #[test]
+    fn accepts_everything_without_rules() {
+        let filter = filter(&[], &[]);
+        assert!(filter.test_path("a.txt", false));
+        assert!(filter.test_path("a/b", true));
+    }
+
+    #[test]
+    fn excludes_files_and_directories() {
+        let filter = filter(&[], &["**/*.log", "build/"]);
+        assert!(!filter.test_path("a.log", false));
+        assert!(!filter.test_path("a/b.log", false));
+        assert!(!filter.test_path("build", true));
+        assert!(!filter.test_path("build/a.txt", false));
+        assert!(filter.test_path("a.txt", false));
+        assert!(filter.test_path("build.txt", false));
+    }
+
+    #[test]
+    fn negation_re_includes_in_order() {
+        let filter = filter(&[], &["*.log", "!keep.log"]);
+        assert!(!filter.test_path("a.log", false));
+        assert!(filter.test_path("keep.log", false));
+        assert!(filter.test_path("a/keep.log", false));
+
+        // 后面的规则优先，被重新排除
+        let filter = self::filter(&[], &["*.log", "!keep.log", "a/"]);
+        assert!(filter.test_path("keep.log", false));
+        assert!(!filter.test_path("a/keep.log", false));
+    }
+
+    #[test]
+    fn negation_re_includes_files_in_excluded_directories() {
+        let filter = filter(&[], &["build/", "!build/keep.txt"]);
+        assert!(!filter.test_path("build/a.txt", false));
+        assert!(filter.test_path("build/keep.txt", false));
+    }
+
+    #[test]
+    fn include_limits_files_but_not_directories() {
+        let filter = filter(&["*.txt", "!secret.txt"], &[]);
+        assert!(filter.test_path("a.txt", false));
+        assert!(filter.test_path("a/b.txt", false));
+        assert!(!filter.test_path("a.log", false));
+        assert!(!filter.test_path("secret.txt", false));
+        assert!(filter.test_path("a", true));
+    }
+
+    #[test]
+    fn exclude_overrides_include() {
+        let filter = filter(&["docs/"], &["*.tmp", "!docs/keep.tmp"]);
+        assert!(filter.test_path("docs/a.md", false));
+        assert!(!filter.test_path("docs/a.tmp", false));
+        assert!(filter.test_path("docs/keep.tmp", false));
+        assert!(!filter.test_path("src/a.md", false));
+    }
+
+    #[test]
+    fn combines_with_file_filters() {
+        let rules = vec!["!\\.bak$".to_owned()];
+        let filter = RuleFilter::new_with_globs(&rules, &[], &["*.log".to_owned()]).unwrap();
+        assert!(filter.test_path("a.txt", false));
+        assert!(!filter.test_path("a.bak", false));
+        assert!(!filter.test_path("a.log", false));
+    }
+
+    #[test]
+    fn rejects_invalid_rules() {
+        let error = RuleFilter::new_with_globs(&[], &[], &["a{b".to_owned()]).err().unwrap();
+        assert!(error.to_string().contains("invalid exclude rule 'a{b'"));
+    }
+}
@@ -0,0 +1,377 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::io::Error;
+use std::io::ErrorKind;
+use std::io::Read;
+use std::io::Result;
+use std::net::TcpStream;
+use std::path::Path;
+use std::sync::Mutex;
+
+use ssh2::CheckResult;
+use ssh2::FileStat;
+use ssh2::KnownHostFileKind;
+use ssh2::Session;
+use ssh2::Sftp;
+
+use crate::backend::Backend;
+use crate::file::File;
+use crate::progress;
+
+/// SFTP服务器的配置，密码从环境变量SFTP_PASSWORD中读取，私钥的密码从环境变量SFTP_PASSPHRASE中读取
+#[derive(Clone)]
+pub struct SftpConfig {
+    pub host: String,
+    pub port: u16,
+    pub username: String,
+    /// 私钥文件路径，为空时依次尝试密码和ssh-agent认证
+    pub private_key: String,
+    /// known_hosts文件路径，用来验证服务器的公钥
+    pub known_hosts: String,
+    /// known_hosts文件不存在或者服务器不在文件中时是否继续连接(只输出警告)，默认为false即拒绝连接
+    pub accept_unknown_host_keys: bool,
+}
+
+struct Connection {
+    // Session需要和Sftp一起保留，否则连接会被关闭
+    _session: Session,
+    sftp: Sftp,
+}
+
+/// 通过SFTP上传文件，每个同时执行操作的线程使用一个单独的SSH连接(按需建立，用完后放回连接池)，
+/// 所以最多建立threads个连接，上传不会因为共用一个连接而被串行化
+pub struct SftpBackend {
+    config: SftpConfig,
+    target_dir: String,
+    /// 空闲的连接
+    idle: Mutex<Vec<Connection>>,
+}
+
+impl SftpBackend {
+    pub fn new(config: &SftpConfig, target_dir: &str) -> Result<SftpBackend> {
+        // 先建立一个连接，这样配置错误时可以立即报错
+        let connection = SftpBackend::connect(config, true)?;
+        let target_dir = target_dir.trim_end_matches('/').to_owned();
+
+        Ok(SftpBackend { config: config.clone(), target_dir, idle: Mutex::new(vec![connection]) })
+    }
+
+    /// 建立一个新的连接，warn为false时不重复输出公钥验证的警告
+    fn connect(config: &SftpConfig, warn: bool) -> Result<Connection> {
+        let stream = TcpStream::connect((&config.host[..], config.port))?;
+        let mut session = Session::new()?;
+        session.set_tcp_stream(stream);
+        session.handshake()?;
+
+        SftpBackend::check_host_key(&session, config, warn)?;
+
+        let password = env::var("SFTP_PASSWORD").ok();
+        if !config.private_key.is_empty() {
+            let passphrase = env::var("SFTP_PASSPHRASE").ok();
+            session.userauth_pubkey_file(&config.username, None, Path::new(&expand_home(&config.private_key)), passphrase.as_deref())?;
+        } else if let Some(password) = password {
+            session.userauth_password(&config.username, &password)?;
+        } else {
+            session.userauth_agent(&config.username)?;
+        }
+
+        if !session.authenticated() {
+            return Err(Error::new(ErrorKind::PermissionDenied, format!("failed to authenticate as {}", config.username)));
+        }
+
+        let sftp = session.sftp()?;
+
+        Ok(Connection { _session: session, sftp })
+    }
+
+    /// 从连接池中取出一个连接(没有空闲的连接时新建一个)执行操作，成功后放回连接池<br/>
+    /// 执行失败的连接可能已经断开，直接丢弃，下次使用时重新建立
+    fn with_sftp<R>(&self, f: impl FnOnce(&Sftp) -> Result<R>) -> Result<R> {
+        let idle = self.idle.lock().unwrap().pop();
+        let connection = match idle {
+            Some(connection) => connection,
+            None => SftpBackend::connect(&self.config, false)?,
+        };
+
+        let result = f(&connection.sftp)?;
+        self.idle.lock().unwrap().push(connection);
+
+        Ok(result)
+    }
+
+    /// 使用known_hosts文件验证服务器公钥<br/>
+    /// known_hosts文件不存在或者服务器不在文件中时，除非开启了accept-unknown-host-keys，否则拒绝连接
+    fn check_host_key(session: &Session, config: &SftpConfig, warn: bool) -> Result<()> {
+        let known_hosts_file = expand_home(&config.known_hosts);
+        if !Path::new(&known_hosts_file).is_file() {
+            if !config.accept_unknown_host_keys {
+                return Err(Error::new(ErrorKind::PermissionDenied, format!("the known_hosts file {} does not exist, cannot verify the host key of {} (set 'sftp.accept-unknown-host-keys' to skip the verification)", known_hosts_file, config.host)));
+            }
+            if warn {
+                progress!("警告: 未找到known_hosts文件({})，跳过服务器公钥验证", known_hosts_file);
+            }
+            return Ok(());
+        }
+
+        let mut known_hosts = session.known_hosts()?;
+        known_hosts.read_file(Path::new(&known_hosts_file), KnownHostFileKind::OpenSSH)?;
+
+        let (key, _) = session.host_key()
+            .ok_or_else(|| Error::other("failed to get the host key of the server"))?;
+
+        match known_hosts.check_port(&config.host, config.port, key) {
+            CheckResult::Match => Ok(()),
+            CheckResult::NotFound if !config.accept_unknown_host_keys => Err(Error::new(ErrorKind::PermissionDenied, format!("the host key of {} is not in the known_hosts file (set 'sftp.accept-unknown-host-keys' to skip the verification)", config.host))),
+            CheckResult::NotFound => {
+                if warn {
+                    progress!("警告: 服务器{}不在known_hosts文件中，跳过服务器公钥验证", config.host);
+                }
+                Ok(())
+            },
+            CheckResult::Mismatch => Err(Error::new(ErrorKind::PermissionDenied, format!("the host key of {} does not match the known_hosts file", config.host))),
+            CheckResult::Failure => Err(Error::other(format!("failed to check the host key of {}", config.host))),
+        }
+    }
+
+    fn remote_path(&self, path: &str) -> String {
+        format!("{}/{}", self.target_dir, path)
+    }
+
+    /// 创建远端目录(包括所有父目录)
+    fn mkdirs(sftp: &Sftp, path: &str) -> Result<()> {
+        let mut current = String::new();
+
+        for name in path.split('/') {
+            if name.is_empty() {
+                current.push('/');
+                continue;
+            }
+
+            current.push_str(name);
+
+            match sftp.stat(Path::new(&current)) {
+                Ok(stat) if stat.is_dir() => (),
+                Ok(_) => return Err(Error::new(ErrorKind::AlreadyExists, format!("remote path is not a directory: {}", current))),
+                Err(_) => sftp.mkdir(Path::new(&current), 0o755)?,
+            }
+
+            current.push('/');
+        }
+
+        Ok(())
+    }
+
+    /// 确保远端文件的父目录存在
+    fn make_parent(sftp: &Sftp, path: &str) -> Result<()> {
+        match path.rfind('/') {
+            Some(pos) if pos > 0 => SftpBackend::mkdirs(sftp, &path[..pos]),
+            _ => Ok(()),
+        }
+    }
+
+    /// 递归删除远端目录
+    fn remove_dir_all(sftp: &Sftp, path: &str) -> Result<()> {
+        for (child, stat) in sftp.readdir(Path::new(path))? {
+            if stat.is_dir() {
+                SftpBackend::remove_dir_all(sftp, &child.to_string_lossy())?;
+            } else {
+                sftp.unlink(&child)?;
+            }
+        }
+
+        sftp.rmdir(Path::new(path))?;
+
+        Ok(())
+    }
+
+    fn put(&self, local: &File, remote: &str) -> Result<()> {
+        self.with_sftp(|sftp| {
+            SftpBackend::make_parent(sftp, remote)?;
+
+            let mut reader = fs::File::open(local.path())?;
+            let mut writer = sftp.create(Path::new(remote))?;
+            io::copy(&mut reader, &mut writer)?;
+
+            Ok(())
+        })
+    }
+}
+
+impl Backend for SftpBackend {
+    fn upload_file(&self, local: &File, path: &str) -> Result<()> {
+        self.put(local, &self.remote_path(path))
+    }
+
+    fn delete_file(&self, path: &str) -> Result<()> {
+        self.with_sftp(|sftp| {
+            match sftp.unlink(Path::new(&self.remote_path(path))).map_err(Error::from) {
+                Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            }
+        })
+    }
+
+    fn make_dir(&self, path: &str) -> Result<()> {
+        self.with_sftp(|sftp| SftpBackend::mkdirs(sftp, &self.remote_path(path)))
+    }
+
+    fn delete_dir(&self, path: &str) -> Result<()> {
+        let remote = self.remote_path(path);
+
+        self.with_sftp(|sftp| {
+            if sftp.stat(Path::new(&remote)).is_err() {
+                return Ok(());
+            }
+
+            SftpBackend::remove_dir_all(sftp, &remote)
+        })
+    }
+
+    fn move_file(&self, from: &str, to: &str) -> Result<()> {
+        let from = self.remote_path(from);
+        let to = self.remote_path(to);
+
+        self.with_sftp(|sftp| {
+            SftpBackend::make_parent(sftp, &to)?;
+
+            // 部分服务器不支持覆盖已有文件，所以先删除目标文件
+            if sftp.stat(Path::new(&to)).is_ok() {
+                sftp.unlink(Path::new(&to))?;
+            }
+
+            sftp.rename(Path::new(&from), Path::new(&to), None)?;
+
+            Ok(())
+        })
+    }
+
+    fn chmod_file(&self, path: &str, mode: u32) -> Result<()> {
+        let stat = FileStat { size: None, uid: None, gid: None, perm: Some(mode), atime: None, mtime: None };
+
+        self.with_sftp(|sftp| Ok(sftp.setstat(Path::new(&self.remote_path(path)), stat)?))
+    }
+
+    fn upload_state(&self, local: &File, name: &str) -> Result<()> {
+        self.put(local, &self.remote_path(name))
+    }
+
+    fn download_state(&self, name: &str, local: &File) -> Result<bool> {
+        self.with_sftp(|sftp| {
+            let mut reader = match sftp.open(Path::new(&self.remote_path(name))) {
+                Ok(reader) => reader,
+                Err(e) => {
+                    let e = Error::from(e);
+                    return if e.kind() == ErrorKind::NotFound { Ok(false) } else { Err(e) };
+                },
+            };
+
+            // 先完整读取内容再原子地写入，下载中断时不会留下不完整的状态文件
+            let mut contents = Vec::new();
+            reader.read_to_end(&mut contents)?;
+
+            if let Some(parent) = local.parent()? {
+                parent.mkdirs()?;
+            }
+            local.write_atomically(&contents)?;
+
+            Ok(true)
+        })
+    }
+}
+
+/// 把路径开头的~替换为用户目录
+fn expand_home(path: &str) -> String {
+    match (path.strip_prefix("~/"), env::var("HOME")) {
+        (Some(rest), Ok(home)) => format!("{}/{}", home.trim_end_matches('/'), rest),
+        _ => path.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+    use crate::test_utils::TempDir;
+
+    #[test]
+    fn expands_home() {
+        let home = env::var("HOME").unwrap();
+        assert_eq!(expand_home("~/.ssh/known_hosts"), format!("{}/.ssh/known_hosts", home.trim_end_matches('/')));
+        assert_eq!(expand_home("/etc/ssh/known_hosts"), "/etc/ssh/known_hosts");
+        assert_eq!(expand_home("~other/known_hosts"), "~other/known_hosts");
+    }
+
+    // 以下测试需要一个可以访问的sshd，比如:
+    // docker run -d -p 2222:22 atmoz/sftp test:test:::upload
+    // ssh-keyscan -p 2222 127.0.0.1 > /tmp/sftp_known_hosts
+    // SFTP_TEST_HOST=127.0.0.1 SFTP_TEST_PORT=2222 SFTP_TEST_USER=test SFTP_PASSWORD=test \
+    //     SFTP_TEST_KNOWN_HOSTS=/tmp/sftp_known_hosts SFTP_TEST_DIR=upload cargo test -- --ignored
+
+    fn test_config(known_hosts: &str, accept_unknown_host_keys: bool) -> SftpConfig {
+        SftpConfig {
+            host: env::var("SFTP_TEST_HOST").unwrap_or_else(|_| "127.0.0.1".to_owned()),
+            port: env::var("SFTP_TEST_PORT").ok().and_then(|p| p.parse().ok()).unwrap_or(2222),
+            username: env::var("SFTP_TEST_USER").unwrap_or_else(|_| "test".to_owned()),
+            private_key: String::new(),
+            known_hosts: known_hosts.to_owned(),
+            accept_unknown_host_keys,
+        }
+    }
+
+    fn target_dir() -> String {
+        format!("{}/incremental-upload-test-{}", env::var("SFTP_TEST_DIR").unwrap_or_else(|_| "upload".to_owned()), std::process::id())
+    }
+
+    #[test]
+    #[ignore]
+    fn rejects_unknown_host_keys() {
+        let dir = TempDir::new("sftp-known-hosts");
+        let missing = dir.file("missing").path();
+        let empty = dir.file("empty");
+        empty.write_atomically(b"").unwrap();
+
+        for known_hosts in [&missing, &empty.path()] {
+            let error = SftpBackend::new(&test_config(known_hosts, false), &target_dir()).err().unwrap();
+            assert_eq!(error.kind(), ErrorKind::PermissionDenied);
+
+            assert!(SftpBackend::new(&test_config(known_hosts, true), &target_dir()).is_ok());
+        }
+    }
+
+    #[test]
+    #[ignore]
+    fn uploads_with_one_connection_per_thread() {
+        let known_hosts = env::var("SFTP_TEST_KNOWN_HOSTS").unwrap_or_else(|_| "~/.ssh/known_hosts".to_owned());
+        let backend = SftpBackend::new(&test_config(&known_hosts, false), &target_dir()).unwrap();
+
+        let dir = TempDir::new("sftp-upload");
+        let local = dir.file("upload.txt");
+        local.write_atomically(b"hello sftp").unwrap();
+        let downloaded = dir.file("download.txt");
+
+        thread::scope(|scope| {
+            for i in 0..4 {
+                let (backend, local) = (&backend, &local);
+                scope.spawn(move || backend.upload_file(local, &format!("d{}/f.txt", i)).unwrap());
+            }
+        });
+        assert!(!backend.idle.lock().unwrap().is_empty());
+
+        for i in 0..4 {
+            assert!(backend.download_state(&format!("d{}/f.txt", i), &downloaded).unwrap());
+            assert_eq!(downloaded.read().unwrap(), "hello sftp");
+        }
+
+        backend.move_file("d0/f.txt", "moved/f.txt").unwrap();
+        backend.chmod_file("moved/f.txt", 0o600).unwrap();
+        assert!(!backend.download_state("d0/f.txt", &downloaded).unwrap());
+        assert!(backend.download_state("moved/f.txt", &downloaded).unwrap());
+
+        backend.delete_file("moved/f.txt").unwrap();
+        backend.delete_file("moved/f.txt").unwrap();
+        assert!(!backend.download_state("moved/f.txt", &downloaded).unwrap());
+        backend.delete_dir("").unwrap();
+    }
+}
@@ -1,11 +1,23 @@
+use crate::chunker::Chunk;
 use crate::file::File;
 use crate::hash_cache::HashCache;
+use crate::walk_filter::WalkFilter;
+use std::fs;
 use std::io::Result;
+use std::time::Duration;
 
 pub struct FileData {
     pub length: u64,
-    pub sha1: String,
+    pub hash: String,
     pub modified: u64,
+    /// 纳秒精度的修改时间，旧版本的状态文件中只有秒(modified)
+    pub modified_ns: Option<u64>,
+    /// 权限位(比如0o755)，旧版本的状态文件以及不支持POSIX权限的平台上为None
+    pub mode: Option<u32>,
+    /// 符号链接指向的路径，只有preserve模式下的符号链接才有，此时length和hash没有意义
+    pub link_target: Option<String>,
+    /// 分块上传的文件的分块列表
+    pub chunks: Option<Vec<Chunk>>,
 }
 
 pub struct DirData {
@@ -19,13 +31,33 @@ pub struct SimpleFile {
 }
 
 impl SimpleFile {
-    pub fn new_file(name: &str, length: u64, sha1: &str, modified: u64) -> SimpleFile {
+    pub fn new_file(name: &str, length: u64, hash: &str, modified: u64, modified_ns: Option<u64>, mode: Option<u32>) -> SimpleFile {
         SimpleFile {
             name: name.to_owned(), 
             file_data: Some(FileData {
                 length,
-                sha1: sha1.to_owned(), 
+                hash: hash.to_owned(), 
                 modified,
+                modified_ns,
+                mode,
+                link_target: None,
+                chunks: None,
+            }),
+            dir_data: None
+        }
+    }
+
+    pub fn new_symlink(name: &str, link_target: &str) -> SimpleFile {
+        SimpleFile {
+            name: name.to_owned(),
+            file_data: Some(FileData {
+                length: 0,
+                hash: String::new(),
+                modified: 0,
+                modified_ns: None,
+                mode: None,
+                link_target: Some(link_target.to_owned()),
+                chunks: None,
             }),
             dir_data: None
         }
@@ -48,17 +80,26 @@ impl SimpleFile {
         } else {
             file.sha1()?
         };
-        Ok(SimpleFile::new_file(file.name(), file.length()?, &hash, file.modified()?))
+        Ok(SimpleFile::new_file(file.name(), file.length()?, &hash, file.modified()?, Some(file.modified_ns()?), file.mode()?))
     }
 
-    pub fn from_real_directory(dir: &File, extra: Option<(&HashCache, &File, bool)>) -> Result<SimpleFile> {
+    /// 读取符号链接本身(不跟随链接)
+    pub fn from_real_symlink(file: &File) -> Result<SimpleFile> {
+        Ok(SimpleFile::new_symlink(file.name(), &read_link_target(file)?))
+    }
+
+    /// walk_filter: 用来跳过被忽略的文件和目录
+    pub fn from_real_directory(dir: &File, extra: Option<(&HashCache, &File, bool)>, walk_filter: Option<&WalkFilter>) -> Result<SimpleFile> {
         let files = dir.files()?
             .filter_map(|v| v.ok())
+            .filter(|v| !walk_filter.is_some_and(|f| f.is_ignored(v)))
             .filter_map(|v: File| -> Option<SimpleFile> {
-                if v.is_dir() {
-                    Some(SimpleFile::from_real_directory(&v, extra).map_or_else(|_e| None, |v| Some(v))?)
+                if walk_filter.is_some_and(|f| f.preserves(&v)) {
+                    SimpleFile::from_real_symlink(&v).ok()
+                } else if v.is_dir() {
+                    SimpleFile::from_real_directory(&v, extra, walk_filter).ok()
                 } else if v.is_file() {
-                    Some(SimpleFile::from_real_file(&v, extra).map_or_else(|_e| None, |v| Some(v))?)
+                    SimpleFile::from_real_file(&v, extra).ok()
                 } else {
                     None
                 }
@@ -75,6 +116,10 @@ impl SimpleFile {
         self.dir_data.is_some()
     }
 
+    pub fn is_symlink(&self) -> bool {
+        self.file_data.as_ref().is_some_and(|f| f.link_target.is_some())
+    }
+
     pub fn as_file(&self) -> Option<&FileData> {
         self.file_data.as_ref()
     }
@@ -120,20 +165,31 @@ impl PartialEq for SimpleFile {
 }
 
 impl FileData {
-    pub fn new(length: u64, sha1: String, modified: u64,) -> FileData {
-        FileData { length, sha1, modified }
+    pub fn new(length: u64, hash: String, modified: u64,) -> FileData {
+        FileData { length, hash, modified, modified_ns: None, mode: None, link_target: None, chunks: None }
+    }
+
+    /// 对比记录的修改时间和文件当前的修改时间，相差不超过tolerance时视为一致<br/>
+    /// 旧版本的状态文件只记录了秒，此时按秒进行对比
+    pub fn mtime_matches(&self, file: &File, tolerance: Duration) -> bool {
+        let tolerance = tolerance.as_nanos() as u64;
+
+        match self.modified_ns {
+            Some(modified_ns) => file.modified_ns().is_ok_and(|ns| ns.abs_diff(modified_ns) <= tolerance),
+            None => file.modified().is_ok_and(|secs| secs.abs_diff(self.modified).saturating_mul(1_000_000_000) <= tolerance),
+        }
     }
 }
 
 impl Clone for FileData {
     fn clone(&self) -> Self {
-        Self { length: self.length.clone(), sha1: self.sha1.clone(), modified: self.modified.clone() }
+        Self { length: self.length, hash: self.hash.clone(), modified: self.modified, modified_ns: self.modified_ns, mode: self.mode, link_target: self.link_target.clone(), chunks: self.chunks.clone() }
     }
 }
 
 impl PartialEq for FileData {
     fn eq(&self, other: &Self) -> bool {
-        self.length == other.length && self.sha1 == other.sha1 && self.modified == other.modified
+        self.length == other.length && self.hash == other.hash && self.modified == other.modified && self.modified_ns == other.modified_ns && self.mode == other.mode && self.link_target == other.link_target && self.chunks == other.chunks
     }
 }
 
@@ -218,3 +274,50 @@ impl PartialEq for DirData {
     }
 }
 
+/// 读取符号链接指向的路径，统一使用/作为分隔符
+pub fn read_link_target(file: &File) -> Result<String> {
+    Ok(fs::read_link(file.get_raw())?.to_string_lossy().replace("\\", "/"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TempDir;
+    use crate::test_utils::set_mtime;
+
+    const SECS: u64 = 1_700_000_000;
+
+    fn recorded(modified_ns: Option<u64>) -> FileData {
+        FileData { modified_ns, ..FileData::new(1, "".to_owned(), SECS) }
+    }
+
+    #[test]
+    fn compares_nanosecond_mtimes() {
+        let dir = TempDir::new("mtime-ns");
+        let file = dir.write("a.txt", "a");
+        let base = Duration::from_secs(SECS) + Duration::from_millis(500);
+        set_mtime(&file, base);
+
+        let data = recorded(Some(base.as_nanos() as u64));
+        assert!(data.mtime_matches(&file, Duration::ZERO));
+
+        // 同一秒内的修改同样可以被发现
+        set_mtime(&file, base + Duration::from_millis(1));
+        assert!(!data.mtime_matches(&file, Duration::ZERO));
+        assert!(data.mtime_matches(&file, Duration::from_millis(2)));
+    }
+
+    #[test]
+    fn compares_legacy_mtimes_in_seconds() {
+        let dir = TempDir::new("mtime-secs");
+        let file = dir.write("a.txt", "a");
+        set_mtime(&file, Duration::from_secs(SECS) + Duration::from_millis(500));
+
+        let data = recorded(None);
+        assert!(data.mtime_matches(&file, Duration::ZERO));
+
+        set_mtime(&file, Duration::from_secs(SECS + 1));
+        assert!(!data.mtime_matches(&file, Duration::ZERO));
+        assert!(data.mtime_matches(&file, Duration::from_secs(1)));
+    }
+}
@@ -0,0 +1,119 @@
+use std::io::Read;
+use std::io::Result;
+use std::io::Write;
+
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+
+/// 状态文件的压缩方式
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum StateCompression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+impl StateCompression {
+    pub fn from_name(name: &str) -> Option<StateCompression> {
+        match &name.to_lowercase()[..] {
+            "none" => Some(StateCompression::None),
+            "gzip" | "gz" => Some(StateCompression::Gzip),
+            "zstd" | "zst" => Some(StateCompression::Zstd),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            StateCompression::None => "none",
+            StateCompression::Gzip => "gzip",
+            StateCompression::Zstd => "zstd",
+        }
+    }
+
+    /// 根据扩展名判断压缩方式: .gz为gzip，.zst为zstd，其它为不压缩
+    pub fn from_path(path: &str) -> StateCompression {
+        let path = path.to_lowercase();
+        if path.ends_with(".gz") {
+            StateCompression::Gzip
+        } else if path.ends_with(".zst") {
+            StateCompression::Zstd
+        } else {
+            StateCompression::None
+        }
+    }
+
+    /// 根据文件头判断压缩方式，这样修改压缩方式后仍然可以读取原来的状态文件
+    pub fn detect(data: &[u8]) -> StateCompression {
+        if data.starts_with(&GZIP_MAGIC) {
+            StateCompression::Gzip
+        } else if data.starts_with(&ZSTD_MAGIC) {
+            StateCompression::Zstd
+        } else {
+            StateCompression::None
+        }
+    }
+
+    pub fn compress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            StateCompression::None => Ok(data.to_vec()),
+            StateCompression::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()
+            },
+            StateCompression::Zstd => zstd::encode_all(data, 0),
+        }
+    }
+
+    /// 解压缩数据，压缩方式由文件头决定
+    pub fn decompress(data: &[u8]) -> Result<Vec<u8>> {
+        match StateCompression::detect(data) {
+            StateCompression::None => Ok(data.to_vec()),
+            StateCompression::Gzip => {
+                let mut decoded = Vec::new();
+                GzDecoder::new(data).read_to_end(&mut decoded)?;
+                Ok(decoded)
+            },
+            StateCompression::Zstd => zstd::decode_all(data),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DATA: &[u8] = br#"{"format-version":2,"files":[{"name":"a.txt","length":1,"hash":"abc","modified":0}]}"#;
+
+    #[test]
+    fn round_trips_every_compression() {
+        for compression in [StateCompression::None, StateCompression::Gzip, StateCompression::Zstd] {
+            let compressed = compression.compress(DATA).unwrap();
+            assert_eq!(StateCompression::detect(&compressed), compression);
+            assert_eq!(StateCompression::decompress(&compressed).unwrap(), DATA);
+        }
+    }
+
+    #[test]
+    fn plain_json_is_not_compressed() {
+        assert_eq!(StateCompression::detect(DATA), StateCompression::None);
+        assert_eq!(StateCompression::detect(b""), StateCompression::None);
+    }
+
+    #[test]
+    fn parses_names_and_paths() {
+        assert_eq!(StateCompression::from_name("GZ"), Some(StateCompression::Gzip));
+        assert_eq!(StateCompression::from_name("zstd"), Some(StateCompression::Zstd));
+        assert_eq!(StateCompression::from_name("none"), Some(StateCompression::None));
+        assert_eq!(StateCompression::from_name("xz"), None);
+
+        assert_eq!(StateCompression::from_path(".state.json.gz"), StateCompression::Gzip);
+        assert_eq!(StateCompression::from_path(".state.json.ZST"), StateCompression::Zstd);
+        assert_eq!(StateCompression::from_path(".state.json"), StateCompression::None);
+    }
+}
@@ -0,0 +1,336 @@
+use std::env;
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::Error;
+use std::io::ErrorKind;
+use std::io::Result;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+
+use json::JsonValue;
+use json::object;
+
+use crate::file::File;
+use crate::progress;
+
+/// 状态文件锁的配置
+#[derive(Clone)]
+pub struct LockConfig {
+    /// 等待其它进程释放锁的最长时间，为0时不等待
+    pub timeout: Duration,
+    /// 超过此时间的锁会被视为过期(比如持有锁的进程被强制结束了)并被接管，None表示锁永不过期
+    pub stale_after: Option<Duration>,
+}
+
+/// 当前进程持有的锁文件(路径, 持有者)，用于在强制退出时删除
+static HELD_LOCKS: Mutex<Vec<(PathBuf, String)>> = Mutex::new(Vec::new());
+
+/// 定期刷新锁文件的后台线程
+struct KeepAlive {
+    stop: Arc<AtomicBool>,
+    thread: JoinHandle<()>,
+}
+
+/// 本地锁文件，释放(drop)时删除锁文件
+pub struct StateLock {
+    file: File,
+    owner: String,
+    keep_alive: Option<KeepAlive>,
+}
+
+impl StateLock {
+    /// 状态文件对应的锁文件: <state-file>.lock
+    pub fn lock_file(state_file: &File) -> File {
+        let mut path = state_file.get_raw().clone().into_os_string();
+        path.push(".lock");
+        File::from(PathBuf::from(path))
+    }
+
+    /// 当前进程的标识: 主机名:进程id:启动时间
+    pub fn owner_id() -> String {
+        let hostname = env::var("HOSTNAME")
+            .or_else(|_| env::var("COMPUTERNAME"))
+            .or_else(|_| fs::read_to_string("/etc/hostname").map(|h| h.trim().to_owned()))
+            .unwrap_or_else(|_| "unknown".to_owned());
+
+        format!("{}:{}:{}", hostname, process::id(), now())
+    }
+
+    /// 尝试创建锁文件，锁文件已经存在且没有过期时返回Ok(None)<br/>
+    /// 过期的锁文件会先被重命名为唯一的文件名，确认重命名的正是过期的锁之后再删除并重新创建，
+    /// 这样多个进程同时接管时只有一个可以成功，也不会误删其它进程刚刚创建(或者刷新)的锁
+    pub fn try_acquire(lock_file: &File, owner: &str, stale_after: Option<Duration>) -> Result<Option<StateLock>> {
+        if let Some(parent) = lock_file.parent()? {
+            parent.mkdirs()?;
+        }
+
+        if let Some(stale_after) = stale_after {
+            if StateLock::is_stale(lock_file, stale_after) {
+                StateLock::take_over(lock_file, owner, stale_after)?;
+            }
+        }
+
+        let mut f = match OpenOptions::new().write(true).create_new(true).open(lock_file.get_raw()) {
+            Ok(f) => f,
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        f.write_all(StateLock::contents(owner, now()).dump().as_bytes())?;
+        f.sync_all()?;
+
+        HELD_LOCKS.lock().unwrap().push((lock_file.get_raw().to_owned(), owner.to_owned()));
+
+        Ok(Some(StateLock { file: lock_file.to_owned(), owner: owner.to_owned(), keep_alive: None }))
+    }
+
+    /// 把过期的锁文件移走，重命名之后锁文件不存在，可以由create_new重新创建<br/>
+    /// 重命名的文件已经不再过期时(其它进程在此期间接管或者刷新了锁)，把它恢复回去
+    fn take_over(lock_file: &File, owner: &str, stale_after: Duration) -> Result<()> {
+        let mut taken = lock_file.get_raw().clone().into_os_string();
+        taken.push(format!(".stale.{}", owner.replace([':', '/', '\\'], "-")));
+        let taken = File::from(PathBuf::from(taken));
+
+        match fs::rename(lock_file.get_raw(), taken.get_raw()) {
+            Ok(_) => (),
+            // 已经被其它进程移走了
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        }
+
+        if StateLock::is_stale(&taken, stale_after) {
+            progress!("警告: 接管过期的锁: {} ({})", lock_file.path(), StateLock::holder(&taken));
+        } else {
+            // 使用硬链接恢复，锁文件已经被重新创建时不会覆盖
+            let _ = fs::hard_link(taken.get_raw(), lock_file.get_raw());
+        }
+
+        fs::remove_file(taken.get_raw())
+    }
+
+    /// 每隔interval刷新一次锁文件中的时间，运行时间超过stale-after时不会被其它进程当成过期的锁接管
+    pub fn keep_alive(&mut self, interval: Duration) {
+        let stop = Arc::new(AtomicBool::new(false));
+        let file = self.file.to_owned();
+        let owner = self.owner.to_owned();
+
+        let thread = {
+            let stop = stop.clone();
+            thread::spawn(move || {
+                let mut next = Instant::now() + interval;
+                while !stop.load(Ordering::SeqCst) {
+                    if Instant::now() < next {
+                        thread::sleep(Duration::from_millis(100));
+                        continue;
+                    }
+
+                    if let Err(e) = StateLock::refresh(&file, &owner) {
+                        progress!("警告: 刷新锁文件失败: {}", e);
+                    }
+                    next = Instant::now() + interval;
+                }
+            })
+        };
+
+        self.keep_alive = Some(KeepAlive { stop, thread });
+    }
+
+    /// 更新锁文件中的刷新时间，锁已经被其它进程接管时返回错误
+    fn refresh(lock_file: &File, owner: &str) -> Result<()> {
+        let holder = StateLock::holder(lock_file);
+        if holder != owner {
+            return Err(Error::other(format!("the lock has been taken over by {}: {}", holder, lock_file.path())));
+        }
+
+        let created = lock_file.read().ok()
+            .and_then(|c| json::parse(&c).ok())
+            .and_then(|c| c["created"].as_u64())
+            .unwrap_or_else(now);
+
+        let mut contents = StateLock::contents(owner, created);
+        contents["refreshed"] = now().into();
+        lock_file.write_atomically(contents.dump().as_bytes())
+    }
+
+    /// 删除当前进程持有的所有锁文件，用于第二次收到中断信号时立即退出的情况
+    pub fn release_held() {
+        for (path, owner) in HELD_LOCKS.lock().unwrap().drain(..) {
+            if StateLock::holder(&File::from(path.clone())) == owner {
+                let _ = fs::remove_file(&path);
+            }
+        }
+    }
+
+    fn contents(owner: &str, created: u64) -> JsonValue {
+        object! { owner: owner, pid: process::id(), created: created }
+    }
+
+    /// 持有锁的进程的标识，无法读取时返回unknown
+    pub fn holder(lock_file: &File) -> String {
+        lock_file.read().ok()
+            .and_then(|c| json::parse(&c).ok())
+            .and_then(|c| c["owner"].as_str().map(|o| o.to_owned()))
+            .unwrap_or_else(|| "unknown".to_owned())
+    }
+
+    /// 锁文件最后一次刷新(或者创建)的时间，锁文件的内容无法解析时使用修改时间
+    fn refreshed(lock_file: &File) -> Option<u64> {
+        let refreshed = lock_file.read().ok()
+            .and_then(|c| json::parse(&c).ok())
+            .and_then(|c| c["refreshed"].as_u64().or_else(|| c["created"].as_u64()));
+
+        refreshed.or_else(|| lock_file.modified().ok())
+    }
+
+    fn is_stale(lock_file: &File, stale_after: Duration) -> bool {
+        StateLock::refreshed(lock_file).is_some_and(|refreshed| now().saturating_sub(refreshed) >= stale_after.as_secs())
+    }
+}
+
+impl Drop for StateLock {
+    fn drop(&mut self) {
+        if let Some(keep_alive) = self.keep_alive.take() {
+            keep_alive.stop.store(true, Ordering::SeqCst);
+            let _ = keep_alive.thread.join();
+        }
+
+        HELD_LOCKS.lock().unwrap().retain(|(path, _)| path != self.file.get_raw());
+
+        // 锁已经被其它进程接管时不能删除
+        if StateLock::holder(&self.file) == self.owner {
+            let _ = fs::remove_file(self.file.get_raw());
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TempDir;
+
+    fn lock_file(name: &str) -> (TempDir, File) {
+        let dir = TempDir::new(&format!("lock-{}", name));
+        let file = StateLock::lock_file(&dir.file("state.json"));
+        (dir, file)
+    }
+
+    fn write_lock(lock_file: &File, owner: &str, created: u64, refreshed: Option<u64>) {
+        let mut contents = StateLock::contents(owner, created);
+        if let Some(refreshed) = refreshed {
+            contents["refreshed"] = refreshed.into();
+        }
+        lock_file.parent().unwrap().unwrap().mkdirs().unwrap();
+        fs::write(lock_file.get_raw(), contents.dump()).unwrap();
+    }
+
+    /// 锁文件所在目录中除了锁文件之外没有残留的文件
+    fn leftovers(lock_file: &File) -> usize {
+        fs::read_dir(lock_file.parent().unwrap().unwrap().get_raw()).unwrap()
+            .filter(|e| e.as_ref().unwrap().path() != *lock_file.get_raw())
+            .count()
+    }
+
+    #[test]
+    fn acquires_and_releases() {
+        let (_dir, file) = lock_file("acquire");
+
+        let lock = StateLock::try_acquire(&file, "a", None).unwrap().unwrap();
+        assert_eq!(StateLock::holder(&file), "a");
+        assert!(StateLock::try_acquire(&file, "b", None).unwrap().is_none());
+
+        drop(lock);
+        assert!(!file.exists());
+        assert!(StateLock::try_acquire(&file, "b", None).unwrap().is_some());
+    }
+
+    #[test]
+    fn takes_over_stale_lock() {
+        let (_dir, file) = lock_file("stale");
+        write_lock(&file, "old", now() - 100, None);
+
+        let lock = StateLock::try_acquire(&file, "new", Some(Duration::from_secs(10))).unwrap();
+        assert!(lock.is_some());
+        assert_eq!(StateLock::holder(&file), "new");
+        assert_eq!(leftovers(&file), 0);
+    }
+
+    #[test]
+    fn does_not_take_over_refreshed_lock() {
+        let (_dir, file) = lock_file("refreshed");
+        write_lock(&file, "old", now() - 100, Some(now()));
+
+        assert!(StateLock::try_acquire(&file, "new", Some(Duration::from_secs(10))).unwrap().is_none());
+        assert!(StateLock::try_acquire(&file, "new", None).unwrap().is_none());
+        assert_eq!(StateLock::holder(&file), "old");
+    }
+
+    #[test]
+    fn restores_lock_that_is_no_longer_stale() {
+        let (_dir, file) = lock_file("restore");
+        write_lock(&file, "old", now() - 100, Some(now()));
+
+        // 判断过期之后，重命名之前锁被刷新了
+        StateLock::take_over(&file, "new", Duration::from_secs(10)).unwrap();
+        assert_eq!(StateLock::holder(&file), "old");
+        assert_eq!(leftovers(&file), 0);
+    }
+
+    #[test]
+    fn concurrent_takeover_has_one_winner() {
+        let (_dir, file) = lock_file("concurrent");
+        write_lock(&file, "old", now() - 100, None);
+
+        let winners = thread::scope(|scope| {
+            let handles = (0..8)
+                .map(|i| {
+                    let file = &file;
+                    scope.spawn(move || StateLock::try_acquire(file, &format!("owner-{}", i), Some(Duration::from_secs(10))).unwrap())
+                })
+                .collect::<Vec<_>>();
+            handles.into_iter().map(|h| h.join().unwrap()).collect::<Vec<_>>()
+        });
+
+        let held = winners.iter().flatten().collect::<Vec<&StateLock>>();
+        assert_eq!(held.len(), 1);
+        assert_eq!(StateLock::holder(&file), held[0].owner);
+    }
+
+    #[test]
+    fn keep_alive_refreshes_the_lock() {
+        let (_dir, file) = lock_file("keep-alive");
+
+        let mut lock = StateLock::try_acquire(&file, "a", Some(Duration::from_secs(1))).unwrap().unwrap();
+        write_lock(&file, "a", now() - 100, None);
+        lock.keep_alive(Duration::from_millis(200));
+        thread::sleep(Duration::from_millis(500));
+
+        assert!(!StateLock::is_stale(&file, Duration::from_secs(10)));
+        assert!(StateLock::try_acquire(&file, "b", Some(Duration::from_secs(10))).unwrap().is_none());
+
+        drop(lock);
+        assert!(!file.exists());
+    }
+
+    #[test]
+    fn refresh_fails_after_takeover() {
+        let (_dir, file) = lock_file("taken");
+        write_lock(&file, "other", now(), None);
+
+        assert!(StateLock::refresh(&file, "a").is_err());
+        assert_eq!(StateLock::holder(&file), "other");
+    }
+}
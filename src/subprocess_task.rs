@@ -6,6 +6,9 @@ use encoding_rs::UTF_8;
 
 use crate::AppResult;
 use crate::file::File;
+use crate::interrupt;
+use crate::progress;
+use crate::retry_policy::RetryPolicy;
 use crate::utils::command_split;
 use crate::variable_replace::VariableReplace;
 
@@ -75,6 +78,51 @@ impl SubprocessTask {
     }
 
     pub fn execute(&mut self, show_output: bool) -> Result<SubprocessResult> {
+        self.execute_with_retry(show_output, &RetryPolicy::none())
+    }
+
+    /// 执行命令，返回码非0且满足重试条件时按照重试策略重新执行，只有最后一次失败才会返回错误
+    pub fn execute_with_retry(&mut self, show_output: bool, retry: &RetryPolicy) -> Result<SubprocessResult> {
+        let mut attempt = 1;
+
+        loop {
+            let result = self.run()?;
+
+            if result.exitcode == 0 {
+                if show_output {
+                    SubprocessTask::print_output(&result);
+                }
+
+                return Ok(result);
+            }
+
+            // 收到中断信号后不再重试
+            if attempt < retry.attempts && retry.should_retry(&result) && !interrupt::is_interrupted() {
+                let delay = retry.backoff_for(attempt);
+                progress!("命令执行失败，返回码({})，{:?}后进行第{}次重试(共{}次): {:?}", 
+                    result.exitcode, delay, attempt, retry.attempts - 1, self.raw_divided);
+
+                if !result.stderr.trim().is_empty() {
+                    progress!("=====stderr=====\n|{}\n================", result.stderr.trim());
+                }
+
+                // 等待期间收到中断信号时不再重试，直接报告本次的失败
+                if interrupt::sleep(delay) {
+                    attempt += 1;
+                    continue;
+                }
+            }
+
+            progress!("\n命令执行失败，返回码({})，以下是详细信息：", result.exitcode);
+            progress!("command-line : {:?}", self.raw_divided);
+            SubprocessTask::print_output(&result);
+
+            return Err(Error::other(format!("process exited with code: {}.", result.exitcode)));
+        }
+    }
+
+    /// 执行一次命令并收集输出，被信号终止的进程会直接返回错误，不会进行重试
+    fn run(&mut self) -> Result<SubprocessResult> {
         let result = &mut self.subprocess
             .output()
             .map_err(|e| {
@@ -82,56 +130,34 @@ impl SubprocessTask {
                 Error::new(e.kind(), msg.to_owned())
             })?;
     
-        let code = result.status.code();
-
-        match code {
-            None => return Err(Error::new(ErrorKind::Interrupted, "process was terminated by a signal.")),
-            Some(exitcode) => {
-                let stderr = &result.stderr;
-                let stdout = &result.stdout;
-                // let stderr = GB18030.decode(stderr).0;
-                // let stdout = GB18030.decode(stdout).0;
-
-                let stderr = UTF_8.decode(stderr).0.replace("\r\n", "\n").replace("\r", "\n").trim().replace("\n", "\n|");
-                let stdout = UTF_8.decode(stdout).0.replace("\r\n", "\n").replace("\r", "\n").trim().replace("\n", "\n|");
-
-                if exitcode != 0 {
-                    println!("\n命令执行失败，返回码({})，以下是详细信息：", exitcode);
-                    println!("command-line : {:?}", self.raw_divided);
-
-                    if stdout.trim().len() > 0 {
-                        println!("=====stdout=====\n|{}", stdout.trim());
-                    }
-
-                    if stderr.trim().len() > 0 {
-                        println!("=====stderr=====\n|{}", stderr.trim());
-                    }
-
-                    if stdout.trim().len() > 0 || stderr.trim().len() > 0 {
-                        println!("================");
-                    }
-
-                    return Err(Error::new(ErrorKind::Other, format!("process exited with code: {}.", exitcode)));
-                } else if show_output {
-                    if stdout.trim().len() > 0 {
-                        println!("=====stdout=====\n|{}", stdout.trim());
-                    }
-
-                    if stderr.trim().len() > 0 {
-                        println!("=====stderr=====\n|{}", stderr.trim());
-                    }
-
-                    if stdout.trim().len() > 0 || stderr.trim().len() > 0 {
-                        println!("================");
-                    }
-                }
+        let exitcode = result.status.code()
+            .ok_or_else(|| Error::new(ErrorKind::Interrupted, "process was terminated by a signal."))?;
 
-                return Ok(SubprocessResult {
-                    stdout: stdout.to_owned(),
-                    stderr: stderr.to_owned(),
-                    exitcode,
-                })
-            }
+        let stderr = &result.stderr;
+        let stdout = &result.stdout;
+        // let stderr = GB18030.decode(stderr).0;
+        // let stdout = GB18030.decode(stdout).0;
+
+        let stderr = UTF_8.decode(stderr).0.replace("\r\n", "\n").replace('\r', "\n").trim().replace('\n', "\n|");
+        let stdout = UTF_8.decode(stdout).0.replace("\r\n", "\n").replace('\r', "\n").trim().replace('\n', "\n|");
+
+        Ok(SubprocessResult { stdout, stderr, exitcode })
+    }
+
+    fn print_output(result: &SubprocessResult) {
+        let stdout = result.stdout.trim();
+        let stderr = result.stderr.trim();
+
+        if !stdout.is_empty() {
+            progress!("=====stdout=====\n|{}", stdout);
+        }
+
+        if !stderr.is_empty() {
+            progress!("=====stderr=====\n|{}", stderr);
+        }
+
+        if !stdout.is_empty() || !stderr.is_empty() {
+            progress!("================");
         }
     }
 }
@@ -0,0 +1,49 @@
+use std::env;
+use std::fs;
+use std::process;
+use std::time::Duration;
+use std::time::SystemTime;
+
+use crate::file::File;
+
+/// 单元测试使用的临时目录: <系统临时目录>/incremental-upload-test-<name>-<进程id><br/>
+/// 创建时会清空原有的内容，离开作用域时整个目录会被删除
+pub struct TempDir {
+    pub dir: File,
+}
+
+impl TempDir {
+    pub fn new(name: &str) -> TempDir {
+        let path = env::temp_dir().join(format!("incremental-upload-test-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+
+        TempDir { dir: File::from(path) }
+    }
+
+    /// 目录下的文件(不会创建文件)
+    pub fn file(&self, path: &str) -> File {
+        self.dir.append(path).unwrap()
+    }
+
+    /// 写入目录下的文件，上级目录不存在时自动创建
+    pub fn write(&self, path: &str, contents: &str) -> File {
+        let file = self.file(path);
+        file.parent().unwrap().unwrap().mkdirs().unwrap();
+        fs::write(file.get_raw(), contents).unwrap();
+        file
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(self.dir.get_raw());
+    }
+}
+
+/// 设置文件或目录的修改时间，modified为距离1970-01-01的时间
+pub fn set_mtime(file: &File, modified: Duration) {
+    // 目录无法以写入的方式打开，只读打开后同样可以设置修改时间(需要是目录的所有者)
+    let handle = fs::File::options().write(file.is_file()).read(!file.is_file()).open(file.get_raw()).unwrap();
+    handle.set_modified(SystemTime::UNIX_EPOCH + modified).unwrap();
+}
@@ -1,4 +1,30 @@
 use std::collections::HashMap;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+static PROGRESS_TO_STDERR: AtomicBool = AtomicBool::new(false);
+
+/// 把进度信息改为输出到标准错误，用于--plan输出计划到标准输出时，避免进度信息混入计划的内容
+pub fn set_progress_to_stderr(enabled: bool) {
+    PROGRESS_TO_STDERR.store(enabled, Ordering::SeqCst);
+}
+
+pub fn progress_to_stderr() -> bool {
+    PROGRESS_TO_STDERR.load(Ordering::SeqCst)
+}
+
+/// 输出进度信息，默认输出到标准输出，调用set_progress_to_stderr(true)之后输出到标准错误
+#[macro_export]
+macro_rules! progress {
+    ($($arg:tt)*) => {
+        if $crate::utils::progress_to_stderr() {
+            eprintln!($($arg)*);
+        } else {
+            println!($($arg)*);
+        }
+    };
+}
 
 pub fn replace_variables(text: &str, vars: &HashMap<String, String>) -> String {
     let mut result = text.to_owned();
@@ -69,4 +95,170 @@ pub fn get_basename(path: &str) -> &str {
     }
 
     path
-}
\ No newline at end of file
+}
+
+/// 解析时间长度，支持ms, s, m, h, d后缀，不带后缀时按秒处理，比如: 500ms, 2s, 1.5m
+pub fn parse_duration(text: &str) -> Option<Duration> {
+    let text = text.trim();
+    let (number, unit) = match text.find(|c: char| !c.is_ascii_digit() && c != '.') {
+        Some(pos) => (&text[..pos], text[pos..].trim()),
+        None => (text, "s"),
+    };
+
+    let number = number.parse::<f64>().ok()?;
+    let seconds = match unit {
+        "ms" => number / 1000.0,
+        "s" => number,
+        "m" => number * 60.0,
+        "h" => number * 3600.0,
+        "d" => number * 86400.0,
+        _ => return None,
+    };
+
+    Duration::try_from_secs_f64(seconds).ok()
+}
+
+/// 解析文件大小，支持B, K(KB), M(MB), G(GB)后缀(按1024换算)，不带后缀时按字节处理，比如: 512K, 1.5GB
+pub fn parse_size(text: &str) -> Option<u64> {
+    let text = text.trim();
+    let (number, unit) = match text.find(|c: char| !c.is_ascii_digit() && c != '.') {
+        Some(pos) => (&text[..pos], text[pos..].trim()),
+        None => (text, "B"),
+    };
+
+    let number = number.parse::<f64>().ok()?;
+    let factor = match &unit.to_uppercase()[..] {
+        "B" => 1u64,
+        "K" | "KB" => 1 << 10,
+        "M" | "MB" => 1 << 20,
+        "G" | "GB" => 1 << 30,
+        "T" | "TB" => 1 << 40,
+        _ => return None,
+    };
+
+    Some((number * factor as f64) as u64)
+}
+
+/// 解析UTC时间，格式为: 2022-01-31, 2022-01-31 08:00, 2022-01-31T08:00:00，返回unix时间戳(秒)
+pub fn parse_datetime(text: &str) -> Option<u64> {
+    let text = text.trim().trim_end_matches('Z');
+    let (date, time) = match text.find([' ', 'T']) {
+        Some(pos) => (&text[..pos], text[pos + 1..].trim()),
+        None => (text, ""),
+    };
+
+    let date = date.split('-').map(|v| v.parse::<i64>().ok()).collect::<Option<Vec<i64>>>()?;
+    let time = if time.is_empty() { vec![] } else { time.split(':').map(|v| v.parse::<i64>().ok()).collect::<Option<Vec<i64>>>()? };
+    if date.len() != 3 || time.len() > 3 {
+        return None;
+    }
+
+    let (year, month, day) = (date[0], date[1], date[2]);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    let days = days_from_civil(year, month, day);
+    let seconds = time.first().unwrap_or(&0) * 3600 + time.get(1).unwrap_or(&0) * 60 + time.get(2).unwrap_or(&0);
+    u64::try_from(days * 86400 + seconds).ok()
+}
+
+/// 由公历日期计算距离1970-01-01的天数(Howard Hinnant的days_from_civil算法)
+pub fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// 由距离1970-01-01的天数计算公历日期(年, 月, 日)，days_from_civil的逆运算
+pub fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("500ms"), Some(Duration::from_millis(500)));
+        assert_eq!(parse_duration("2s"), Some(Duration::from_secs(2)));
+        assert_eq!(parse_duration("1.5m"), Some(Duration::from_secs(90)));
+        assert_eq!(parse_duration("2h"), Some(Duration::from_secs(7200)));
+        assert_eq!(parse_duration("1d"), Some(Duration::from_secs(86400)));
+        assert_eq!(parse_duration(" 30 "), Some(Duration::from_secs(30)));
+        assert_eq!(parse_duration("10 s"), Some(Duration::from_secs(10)));
+    }
+
+    #[test]
+    fn rejects_invalid_durations() {
+        assert_eq!(parse_duration(""), None);
+        assert_eq!(parse_duration("s"), None);
+        assert_eq!(parse_duration("10w"), None);
+        assert_eq!(parse_duration("-1s"), None);
+        assert_eq!(parse_duration("1.2.3s"), None);
+    }
+
+    #[test]
+    fn parses_sizes() {
+        assert_eq!(parse_size("100"), Some(100));
+        assert_eq!(parse_size("100B"), Some(100));
+        assert_eq!(parse_size("512K"), Some(512 * 1024));
+        assert_eq!(parse_size("512kb"), Some(512 * 1024));
+        assert_eq!(parse_size("1.5GB"), Some(3 << 29));
+        assert_eq!(parse_size("2 M"), Some(2 << 20));
+        assert_eq!(parse_size("1T"), Some(1 << 40));
+    }
+
+    #[test]
+    fn rejects_invalid_sizes() {
+        assert_eq!(parse_size(""), None);
+        assert_eq!(parse_size("K"), None);
+        assert_eq!(parse_size("10P"), None);
+        assert_eq!(parse_size("-1K"), None);
+    }
+
+    #[test]
+    fn parses_datetimes() {
+        assert_eq!(parse_datetime("1970-01-01"), Some(0));
+        assert_eq!(parse_datetime("2022-01-31"), Some(1643587200));
+        assert_eq!(parse_datetime("2022-01-31 08:00"), Some(1643587200 + 8 * 3600));
+        assert_eq!(parse_datetime("2022-01-31T08:00:30Z"), Some(1643587200 + 8 * 3600 + 30));
+        assert_eq!(parse_datetime("2024-02-29"), Some(1709164800));
+    }
+
+    #[test]
+    fn rejects_invalid_datetimes() {
+        assert_eq!(parse_datetime(""), None);
+        assert_eq!(parse_datetime("2022-13-01"), None);
+        assert_eq!(parse_datetime("2022-01-32"), None);
+        assert_eq!(parse_datetime("2022-01"), None);
+        assert_eq!(parse_datetime("2022-01-31 08:00:00:00"), None);
+        assert_eq!(parse_datetime("1969-12-31"), None);
+    }
+
+    #[test]
+    fn converts_between_days_and_dates() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+
+        for days in (-800_000..800_000).step_by(97) {
+            let (year, month, day) = civil_from_days(days);
+            assert_eq!(days_from_civil(year, month, day), days);
+        }
+    }
+}
@@ -0,0 +1,423 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::Error;
+use std::io::ErrorKind;
+use std::io::Result;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use ignore::Match;
+use ignore::gitignore::Gitignore;
+use ignore::gitignore::GitignoreBuilder;
+
+use crate::file::File;
+use crate::progress;
+use crate::simple_file::read_link_target;
+use crate::utils::get_dirname;
+
+/// 遇到符号链接时的处理方式
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SymlinkPolicy {
+    /// 当成链接指向的文件或目录处理，指向上级目录的链接(循环)会被跳过
+    Follow,
+    /// 跳过所有符号链接
+    Skip,
+    /// 不跟随链接，把链接本身(以及指向的路径)记录到状态里，使用upload-symlink命令上传
+    Preserve,
+    /// 源目录中存在符号链接时报错
+    Error,
+}
+
+impl SymlinkPolicy {
+    pub fn from_name(name: &str) -> Option<SymlinkPolicy> {
+        match &name.to_lowercase()[..] {
+            "follow" => Some(SymlinkPolicy::Follow),
+            "skip" => Some(SymlinkPolicy::Skip),
+            "preserve" => Some(SymlinkPolicy::Preserve),
+            "error" => Some(SymlinkPolicy::Error),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SymlinkPolicy::Follow => "follow",
+            SymlinkPolicy::Skip => "skip",
+            SymlinkPolicy::Preserve => "preserve",
+            SymlinkPolicy::Error => "error",
+        }
+    }
+}
+
+/// 按照文件属性进行过滤的条件，满足任意一个条件的文件都会被跳过
+#[derive(Clone, Default)]
+pub struct AttributeFilter {
+    /// 跳过大于此大小(字节)的文件
+    pub max_size: Option<u64>,
+    /// 跳过小于此大小(字节)的文件
+    pub min_size: Option<u64>,
+    /// 跳过在此时间(unix时间戳，秒)之前修改的文件
+    pub modified_after: Option<u64>,
+    /// 跳过在此时间(unix时间戳，秒)之后修改的文件
+    pub modified_before: Option<u64>,
+    /// 跳过隐藏的文件和目录(以.开头，或者在Windows上带有隐藏属性)
+    pub skip_hidden: bool,
+    /// 跳过空文件
+    pub skip_empty_files: bool,
+}
+
+impl AttributeFilter {
+    /// 判断文件或者目录是否需要被跳过，大小和修改时间的条件只对文件生效
+    pub fn is_filtered(&self, file: &File) -> bool {
+        if self.skip_hidden && is_hidden(file) {
+            return true;
+        }
+
+        if !file.is_file() {
+            return false;
+        }
+
+        if self.max_size.is_some() || self.min_size.is_some() || self.skip_empty_files {
+            let length = match file.length() {
+                Ok(length) => length,
+                Err(_) => return false,
+            };
+
+            if self.max_size.is_some_and(|max| length > max) ||
+                self.min_size.is_some_and(|min| length < min) ||
+                (self.skip_empty_files && length == 0) {
+                return true;
+            }
+        }
+
+        if self.modified_after.is_some() || self.modified_before.is_some() {
+            let modified = match file.modified() {
+                Ok(modified) => modified,
+                Err(_) => return false,
+            };
+
+            if self.modified_after.is_some_and(|after| modified < after) ||
+                self.modified_before.is_some_and(|before| modified > before) {
+                return true;
+            }
+        }
+
+        false
+    }
+}
+
+#[cfg(windows)]
+fn is_hidden(file: &File) -> bool {
+    use std::os::windows::fs::MetadataExt;
+
+    const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+
+    file.name().starts_with('.') ||
+        file.get_raw().metadata().is_ok_and(|m| m.file_attributes() & FILE_ATTRIBUTE_HIDDEN != 0)
+}
+
+#[cfg(not(windows))]
+fn is_hidden(file: &File) -> bool {
+    file.name().starts_with('.')
+}
+
+/// 扫描源目录时使用的过滤器，被过滤的文件和目录不会被扫描，也不会计算hash
+pub struct WalkFilter {
+    base_path: File,
+    attributes: AttributeFilter,
+    symlinks: SymlinkPolicy,
+    /// 源目录中各级目录下的忽略文件的文件名，比如: .gitignore, .uploadignore
+    ignore_files: Vec<String>,
+    /// 每个目录(相对路径)下的忽略规则，目录下没有忽略文件时为None
+    rules: Mutex<HashMap<String, Option<Arc<Gitignore>>>>,
+}
+
+impl WalkFilter {
+    pub fn new(base_path: &File, ignore_files: &[String], attributes: &AttributeFilter, symlinks: SymlinkPolicy) -> WalkFilter {
+        WalkFilter {
+            base_path: base_path.clone(),
+            attributes: attributes.clone(),
+            symlinks,
+            ignore_files: ignore_files.to_vec(),
+            rules: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// 判断文件或者目录是否需要被跳过
+    pub fn is_ignored(&self, file: &File) -> bool {
+        if file.is_symlink() {
+            match self.symlinks {
+                SymlinkPolicy::Skip => return true,
+                SymlinkPolicy::Follow if file.is_dir() && is_symlink_loop(file) => {
+                    progress!("警告: 跳过循环的符号链接: {}", file.relativized_by(&self.base_path));
+                    return true;
+                },
+                _ => (),
+            }
+        }
+
+        if !self.preserves(file) && self.attributes.is_filtered(file) {
+            return true;
+        }
+
+        if self.ignore_files.is_empty() {
+            return false;
+        }
+
+        let path = file.relativized_by(&self.base_path);
+        let is_dir = file.is_dir();
+
+        // 和git一样，越深层的目录下的规则优先级越高
+        let mut dir = get_dirname(&path);
+        loop {
+            if let Some(rules) = self.rules_of(dir.unwrap_or("")) {
+                match rules.matched(file.get_raw(), is_dir) {
+                    Match::Ignore(_) => return true,
+                    Match::Whitelist(_) => return false,
+                    Match::None => (),
+                }
+            }
+
+            match dir {
+                Some(d) => dir = get_dirname(d),
+                None => break,
+            }
+        }
+
+        false
+    }
+
+    /// 判断file是否需要作为符号链接本身来处理(preserve模式)，而不是跟随链接
+    pub fn preserves(&self, file: &File) -> bool {
+        self.symlinks == SymlinkPolicy::Preserve && file.is_symlink()
+    }
+
+    /// error模式下检查目录中是否存在符号链接(不包括被忽略的文件)，其它模式下不做任何检查
+    pub fn check_symlinks(&self, dir: &File) -> Result<()> {
+        if self.symlinks != SymlinkPolicy::Error {
+            return Ok(());
+        }
+
+        for f in dir.files()? {
+            let f = f?;
+            if self.is_ignored(&f) {
+                continue;
+            }
+
+            if f.is_symlink() {
+                let target = read_link_target(&f).unwrap_or_default();
+                return Err(Error::new(ErrorKind::InvalidData, format!(
+                    "found a symlink in the source directory: {} -> {} (see the config field 'symlinks')", 
+                    f.relativized_by(&self.base_path), target
+                )));
+            }
+
+            if f.is_dir() {
+                self.check_symlinks(&f)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// 读取目录下的忽略文件，dir为相对于源目录的路径
+    fn rules_of(&self, dir: &str) -> Option<Arc<Gitignore>> {
+        let mut rules = self.rules.lock().unwrap();
+
+        if let Some(cached) = rules.get(dir) {
+            return cached.clone();
+        }
+
+        let directory = if dir.is_empty() { self.base_path.clone() } else { self.base_path.append(dir).ok()? };
+        let mut builder = GitignoreBuilder::new(directory.get_raw());
+        let mut found = false;
+
+        for name in &self.ignore_files {
+            if let Ok(ignore_file) = directory.append(name) {
+                if ignore_file.is_file() {
+                    if let Some(e) = builder.add(ignore_file.get_raw()) {
+                        progress!("警告: 忽略文件中有无效的规则({}): {}", ignore_file.path(), e);
+                    }
+                    found = true;
+                }
+            }
+        }
+
+        let result = if found { builder.build().ok().map(Arc::new) } else { None };
+        rules.insert(dir.to_owned(), result.clone());
+
+        result
+    }
+}
+
+/// 判断指向目录的符号链接是否指向了自己的某一级父目录，跟随这样的链接会无限递归下去
+fn is_symlink_loop(link: &File) -> bool {
+    let target = match fs::canonicalize(link.get_raw()) {
+        Ok(target) => target,
+        Err(_) => return false,
+    };
+
+    // 需要使用链接所在的路径(而不是实际路径)逐级向上对比，父目录本身也可能是符号链接
+    let path = match std::env::current_dir() {
+        Ok(cwd) => cwd.join(link.get_raw()),
+        Err(_) => link.get_raw().to_owned(),
+    };
+    let mut parent = path.parent();
+    while let Some(dir) = parent {
+        if fs::canonicalize(dir).is_ok_and(|d| d == target) {
+            return true;
+        }
+        parent = dir.parent();
+    }
+
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::test_utils::TempDir;
+    use crate::test_utils::set_mtime;
+
+    fn ignore_filter(dir: &TempDir) -> WalkFilter {
+        let ignore_files = [".gitignore".to_owned(), ".uploadignore".to_owned()];
+        WalkFilter::new(&dir.dir, &ignore_files, &AttributeFilter::default(), SymlinkPolicy::Follow)
+    }
+
+    fn ignored(filter: &WalkFilter, dir: &TempDir, path: &str) -> bool {
+        filter.is_ignored(&dir.file(path))
+    }
+
+    #[test]
+    fn scopes_ignore_files_to_their_directory() {
+        let dir = TempDir::new("walk-scope");
+        dir.write("sub/.gitignore", "secret.txt\n/top.txt\n");
+        for path in ["secret.txt", "top.txt", "sub/secret.txt", "sub/top.txt", "sub/deeper/secret.txt", "sub/deeper/top.txt"] {
+            dir.write(path, "x");
+        }
+        let filter = ignore_filter(&dir);
+
+        assert!(!ignored(&filter, &dir, "secret.txt"));
+        assert!(!ignored(&filter, &dir, "top.txt"));
+        assert!(ignored(&filter, &dir, "sub/secret.txt"));
+        assert!(ignored(&filter, &dir, "sub/top.txt"));
+        assert!(ignored(&filter, &dir, "sub/deeper/secret.txt"));
+        // 以/开头的规则只匹配忽略文件所在的目录
+        assert!(!ignored(&filter, &dir, "sub/deeper/top.txt"));
+    }
+
+    #[test]
+    fn deeper_rules_override_parent_rules() {
+        let dir = TempDir::new("walk-override");
+        dir.write(".gitignore", "*.log\n");
+        dir.write("sub/.gitignore", "!keep.log\n");
+        dir.write("sub/deeper/.uploadignore", "keep.log\n!*.tmp\n");
+        dir.write(".uploadignore", "*.tmp\n");
+        for path in ["a.log", "a.tmp", "sub/keep.log", "sub/other.log", "sub/a.tmp", "sub/deeper/keep.log", "sub/deeper/a.tmp"] {
+            dir.write(path, "x");
+        }
+        let filter = ignore_filter(&dir);
+
+        assert!(ignored(&filter, &dir, "a.log"));
+        assert!(ignored(&filter, &dir, "a.tmp"));
+        // 子目录中的!规则重新包含了上级目录排除的文件
+        assert!(!ignored(&filter, &dir, "sub/keep.log"));
+        assert!(ignored(&filter, &dir, "sub/other.log"));
+        assert!(ignored(&filter, &dir, "sub/a.tmp"));
+        // 更深的目录又重新排除了上级目录包含的文件
+        assert!(ignored(&filter, &dir, "sub/deeper/keep.log"));
+        assert!(!ignored(&filter, &dir, "sub/deeper/a.tmp"));
+    }
+
+    #[test]
+    fn ignores_directories() {
+        let dir = TempDir::new("walk-dirs");
+        dir.write(".gitignore", "cache/\n");
+        dir.write("cache/a.txt", "x");
+        dir.write("sub/cache/a.txt", "x");
+        dir.write("sub/cache.txt", "x");
+        let filter = ignore_filter(&dir);
+
+        assert!(ignored(&filter, &dir, "cache"));
+        assert!(ignored(&filter, &dir, "sub/cache"));
+        assert!(!ignored(&filter, &dir, "sub/cache.txt"));
+        assert!(!ignored(&filter, &dir, "sub"));
+    }
+
+    #[test]
+    fn ignores_nothing_without_ignore_files() {
+        let dir = TempDir::new("walk-none");
+        dir.write("sub/.gitignore", "*\n");
+        dir.write("sub/a.txt", "x");
+        let filter = WalkFilter::new(&dir.dir, &[], &AttributeFilter::default(), SymlinkPolicy::Follow);
+
+        assert!(!ignored(&filter, &dir, "sub/a.txt"));
+    }
+
+    /// 源目录: empty.txt(0字节), small.txt(5字节), large.txt(100字节), .hidden.txt, .hidden/a.txt, sub/a.txt<br/>
+    /// small.txt, empty.txt和.hidden.txt, large.txt的修改时间分别为1000, 2000, 3000秒，sub目录为1秒(确认目录不会按修改时间过滤)
+    fn attribute_fixture(name: &str) -> TempDir {
+        let dir = TempDir::new(name);
+        set_mtime(&dir.write("empty.txt", ""), Duration::from_secs(2000));
+        set_mtime(&dir.write("small.txt", "small"), Duration::from_secs(1000));
+        set_mtime(&dir.write("large.txt", &"x".repeat(100)), Duration::from_secs(3000));
+        set_mtime(&dir.write(".hidden.txt", "hidden"), Duration::from_secs(2000));
+        dir.write(".hidden/a.txt", "hidden");
+        dir.write("sub/a.txt", "a");
+        set_mtime(&dir.file("sub"), Duration::from_secs(1));
+        dir
+    }
+
+    fn filtered(filter: &AttributeFilter, dir: &TempDir) -> Vec<&'static str> {
+        ["empty.txt", "small.txt", "large.txt", ".hidden.txt", ".hidden", "sub"].into_iter()
+            .filter(|path| filter.is_filtered(&dir.file(path)))
+            .collect()
+    }
+
+    #[test]
+    fn filters_by_size() {
+        let dir = attribute_fixture("attr-size");
+
+        assert_eq!(filtered(&AttributeFilter { max_size: Some(50), ..Default::default() }, &dir), vec!["large.txt"]);
+        assert_eq!(filtered(&AttributeFilter { min_size: Some(5), ..Default::default() }, &dir), vec!["empty.txt"]);
+        assert_eq!(filtered(&AttributeFilter { min_size: Some(6), max_size: Some(99), ..Default::default() }, &dir), vec!["empty.txt", "small.txt", "large.txt"]);
+        assert_eq!(filtered(&AttributeFilter { skip_empty_files: true, ..Default::default() }, &dir), vec!["empty.txt"]);
+    }
+
+    #[test]
+    fn filters_by_modified_time() {
+        let dir = attribute_fixture("attr-mtime");
+
+        // 边界上的时间不会被过滤
+        assert_eq!(filtered(&AttributeFilter { modified_after: Some(2000), ..Default::default() }, &dir), vec!["small.txt"]);
+        assert_eq!(filtered(&AttributeFilter { modified_before: Some(2000), ..Default::default() }, &dir), vec!["large.txt"]);
+        assert_eq!(filtered(&AttributeFilter { modified_after: Some(1500), modified_before: Some(2500), ..Default::default() }, &dir), vec!["small.txt", "large.txt"]);
+    }
+
+    #[test]
+    fn filters_hidden_files_and_directories() {
+        let dir = attribute_fixture("attr-hidden");
+
+        assert_eq!(filtered(&AttributeFilter { skip_hidden: true, ..Default::default() }, &dir), vec![".hidden.txt", ".hidden"]);
+        assert!(filtered(&AttributeFilter::default(), &dir).is_empty());
+    }
+
+    #[test]
+    fn filters_directories_only_by_hidden() {
+        let dir = attribute_fixture("attr-dirs");
+        let filter = AttributeFilter {
+            max_size: Some(0),
+            min_size: Some(1 << 30),
+            modified_after: Some(1 << 40),
+            modified_before: Some(0),
+            skip_hidden: false,
+            skip_empty_files: true,
+        };
+
+        assert!(!filter.is_filtered(&dir.file("sub")));
+        assert!(!filter.is_filtered(&dir.file(".hidden")));
+        assert!(filter.is_filtered(&dir.file("sub/a.txt")));
+    }
+}
@@ -0,0 +1,31 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+use std::process::Output;
+
+/// 创建源目录、目标目录和使用local后端的配置文件，extra_config会被追加到配置文件的末尾
+pub fn setup(name: &str, extra_config: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("incremental-upload-test-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("source")).unwrap();
+    fs::create_dir_all(dir.join("target")).unwrap();
+
+    let config = format!(
+        "source-dir: {0}/source\nstate-file: {0}/state.json\nuse-local-state: true\nuse-remote-state: false\nbackend: local\ntarget-dir: {0}/target\n{1}",
+        dir.display(), extra_config
+    );
+    fs::write(dir.join("config.yml"), config).unwrap();
+
+    dir
+}
+
+pub fn run(dir: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_incremental-upload"))
+        .arg("-c")
+        .arg(dir.join("config.yml"))
+        .args(args)
+        .output()
+        .unwrap()
+}
@@ -0,0 +1,105 @@
+mod common;
+
+use std::fs;
+use std::path::PathBuf;
+
+use common::run;
+
+/// 源目录中包含a.txt和sub/b.txt
+fn setup(name: &str) -> PathBuf {
+    let dir = common::setup(&format!("plan-{}", name), "");
+    fs::create_dir_all(dir.join("source/sub")).unwrap();
+    fs::write(dir.join("source/a.txt"), "a").unwrap();
+    fs::write(dir.join("source/sub/b.txt"), "b").unwrap();
+    dir
+}
+
+fn read(path: PathBuf) -> String {
+    fs::read_to_string(path).unwrap()
+}
+
+#[test]
+fn plan_on_stdout_is_valid_json() {
+    let dir = setup("stdout");
+
+    let output = run(&dir, &["--plan", "json"]);
+    assert!(output.status.success());
+
+    let plan = json::parse(&String::from_utf8(output.stdout).unwrap()).unwrap();
+    assert_eq!(plan["backend"], "local");
+    assert_eq!(plan["operations"].len(), 3);
+
+    // 只输出计划，不会执行任何操作
+    assert!(!dir.join("target/a.txt").exists());
+    assert!(!dir.join("state.json").exists());
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn writes_and_applies_plan() {
+    let dir = setup("apply");
+    let plan_file = dir.join("plan.json");
+    let plan_arg = plan_file.to_str().unwrap();
+
+    // 重复写入时覆盖已有的计划文件
+    assert!(run(&dir, &["--plan", "json", "--plan-output", plan_arg]).status.success());
+    assert!(run(&dir, &["--plan", "json", "--plan-output", plan_arg]).status.success());
+    assert!(json::parse(&read(plan_file.clone())).is_ok());
+
+    assert!(run(&dir, &["--apply-plan", plan_arg]).status.success());
+    assert_eq!(read(dir.join("target/a.txt")), "a");
+    assert_eq!(read(dir.join("target/sub/b.txt")), "b");
+
+    // 执行完成后没有任何差异
+    let output = run(&dir, &["--plan", "json"]);
+    let plan = json::parse(&String::from_utf8(output.stdout).unwrap()).unwrap();
+    assert_eq!(plan["operations"].len(), 0);
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn rejects_out_of_date_plan() {
+    let dir = setup("drift");
+    let plan_file = dir.join("plan.json");
+    let plan_arg = plan_file.to_str().unwrap();
+
+    assert!(run(&dir, &["--plan", "json", "--plan-output", plan_arg]).status.success());
+
+    // 生成计划之后文件又被修改了
+    fs::write(dir.join("source/a.txt"), "changed").unwrap();
+
+    let output = run(&dir, &["--apply-plan", plan_arg]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("the plan is out of date"));
+
+    assert!(!dir.join("target/a.txt").exists());
+    assert!(!dir.join("state.json").exists());
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn rejects_foreign_temp_dir() {
+    let dir = setup("temp-dir");
+    let plan_file = dir.join("plan.json");
+    let plan_arg = plan_file.to_str().unwrap();
+
+    assert!(run(&dir, &["--plan", "json", "--plan-output", plan_arg]).status.success());
+
+    // 手动修改过的计划指向了其它目录，执行结束后这个目录会被删除
+    let precious = dir.join("precious");
+    fs::create_dir_all(&precious).unwrap();
+    let mut plan = json::parse(&read(plan_file.clone())).unwrap();
+    plan["temp-dir"] = precious.to_str().unwrap().into();
+    fs::write(&plan_file, plan.dump()).unwrap();
+
+    let output = run(&dir, &["--apply-plan", plan_arg]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("not a chunk temp directory"));
+    assert!(precious.is_dir());
+    assert!(!dir.join("target/a.txt").exists());
+
+    let _ = fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,78 @@
+mod common;
+
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+use common::run;
+
+/// 保留2个状态备份
+fn setup(name: &str) -> PathBuf {
+    common::setup(&format!("backup-{}", name), "state-backups: 2\n")
+}
+
+/// 修改源目录中的一个文件后上传，返回上传后的状态文件内容
+fn upload(dir: &Path, file: &str) -> String {
+    fs::write(dir.join("source").join(file), file).unwrap();
+    assert!(run(dir, &[]).status.success());
+    fs::read_to_string(dir.join("state.json")).unwrap()
+}
+
+fn read(dir: &Path, name: &str) -> Option<String> {
+    fs::read_to_string(dir.join(name)).ok()
+}
+
+#[test]
+fn rotates_state_backups() {
+    let dir = setup("rotate");
+
+    let first = upload(&dir, "a.txt");
+    assert_eq!(read(&dir, "state.json.1"), None);
+
+    let second = upload(&dir, "b.txt");
+    assert_eq!(read(&dir, "state.json.1"), Some(first.clone()));
+
+    let third = upload(&dir, "c.txt");
+    assert_eq!(read(&dir, "state.json.1"), Some(second.clone()));
+    assert_eq!(read(&dir, "state.json.2"), Some(first));
+
+    // 超出state-backups数量的备份会被删除
+    upload(&dir, "d.txt");
+    assert_eq!(read(&dir, "state.json.1"), Some(third));
+    assert_eq!(read(&dir, "state.json.2"), Some(second));
+    assert_eq!(read(&dir, "state.json.3"), None);
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn restores_state_from_backup() {
+    let dir = setup("restore");
+
+    let first = upload(&dir, "a.txt");
+    let second = upload(&dir, "b.txt");
+
+    assert!(run(&dir, &["--restore-state", "1"]).status.success());
+    assert_eq!(read(&dir, "state.json"), Some(first));
+
+    // 恢复之前的状态文件同样被轮换到备份中，所以恢复操作可以撤销
+    assert_eq!(read(&dir, "state.json.1"), Some(second.clone()));
+    assert!(run(&dir, &["--restore-state", "1"]).status.success());
+    assert_eq!(read(&dir, "state.json"), Some(second));
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn rejects_missing_backup() {
+    let dir = setup("missing");
+
+    let state = upload(&dir, "a.txt");
+
+    let output = run(&dir, &["--restore-state", "2"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("the state backup does not exist"));
+    assert_eq!(read(&dir, "state.json"), Some(state));
+
+    let _ = fs::remove_dir_all(&dir);
+}